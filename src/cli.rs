@@ -1,4 +1,5 @@
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -11,13 +12,90 @@ pub struct Cli {
 
 #[derive(Subcommand)]
 pub enum Command {
+    /// Print the shell function to load in the shell's config
     Alias {
         #[arg(default_value_t = String::from("shit"))]
         name: String,
+        #[arg(
+            long,
+            help = "Replace the alias call in the shell history with the chosen command instead of adding it after (bash, zsh and fish)"
+        )]
+        replace_history: bool,
+        #[arg(
+            long,
+            help = "Bind Esc Esc to put a fix of the command line, or of the previous command when it is empty, into the line (bash, zsh and fish)"
+        )]
+        key_binding: bool,
     },
-    Fix,
+    /// Fix the command in SH_PREV_CMD, the shell function runs it for the previous command
+    Fix {
+        #[arg(
+            long,
+            help = "Re-run the command directly instead of through the shell, faster but without builtins, pipes or expansions"
+        )]
+        raw: bool,
+        #[arg(
+            long,
+            help = "Re-run the command under a pseudo-terminal, for tools that print differently to pipes"
+        )]
+        pty: bool,
+        #[arg(
+            long,
+            help = "Don't run the command, only apply the rules that need no output, for a command line that hasn't run yet"
+        )]
+        text_only: bool,
+        #[arg(
+            long,
+            help = "Run the command a second time in your locale for the Python rules declaring native_locale"
+        )]
+        native_locale: bool,
+    },
+    /// Check the shell setup, the rules and Python, and tell how to fix problems
+    Doctor,
+    /// Show the fixes chosen before, which are suggested first for similar commands
+    History {
+        #[arg(long, default_value_t = 20, help = "Number of latest entries to show")]
+        limit: usize,
+        #[arg(long, help = "Delete the history")]
+        clear: bool,
+    },
+    /// Run the shell recording the output of every command, so fixes don't re-run the command
+    Instant,
+    /// Show how often each rule matched and was chosen, how long it took and how often it failed
+    Stats {
+        #[arg(long, help = "Reset the statistics")]
+        clear: bool,
+    },
+    /// Remove the setup of every shell, and with --purge the configuration and rules
+    Uninstall {
+        #[arg(
+            long,
+            help = "Also delete the configuration and rules in ~/.config/theshit"
+        )]
+        purge: bool,
+        #[arg(long, help = "Only show what would be removed")]
+        dry_run: bool,
+        #[arg(long, help = "Also remove the setup from this file")]
+        rc_file: Option<PathBuf>,
+    },
+    /// Add the shell function to the shell's config and install the default rules
     Setup {
         #[arg(default_value_t = String::from("shit"))]
         name: String,
+        #[arg(
+            long,
+            help = "Replace the alias call in the shell history with the chosen command instead of adding it after (bash, zsh and fish)"
+        )]
+        replace_history: bool,
+        #[arg(
+            long,
+            help = "Bind Esc Esc to put a fix of the command line, or of the previous command when it is empty, into the line (bash, zsh and fish)"
+        )]
+        key_binding: bool,
+        #[arg(
+            long,
+            help = "Write the setup to this file instead of the shell's usual config file"
+        )]
+        rc_file: Option<PathBuf>,
     },
 }
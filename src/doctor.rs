@@ -0,0 +1,396 @@
+//! `theshit doctor`: checks the things `shit` depends on and tells how to fix what's wrong.
+use crate::fix;
+use crate::misc;
+use crate::shells::{self, Removal, Shell};
+use crossterm::style::Stylize;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    Pass,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    verdict: Verdict,
+    message: String,
+    hint: Option<String>,
+}
+
+impl Check {
+    fn pass(message: impl Into<String>) -> Self {
+        Check {
+            verdict: Verdict::Pass,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check {
+            verdict: Verdict::Warn,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check {
+            verdict: Verdict::Fail,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self.verdict {
+            Verdict::Pass => "[ ok ]".green(),
+            Verdict::Warn => "[warn]".yellow(),
+            Verdict::Fail => "[fail]".red(),
+        };
+        write!(f, "{label} {}", self.message)?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n       {}", hint.as_str().dark_grey())?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs every check and prints its verdict. Returns false if any check failed.
+pub fn run(shell_name: Option<&str>) -> bool {
+    let mut checks = Vec::new();
+    let shell = check_shell(shell_name, &mut checks);
+    if let Some(shell) = &shell {
+        checks.push(check_setup(shell));
+    }
+    checks.push(check_previous_command(
+        std::env::var_os("SH_PREV_CMD").is_some(),
+    ));
+    let has_python_rules = match dirs::config_dir() {
+        Some(config_dir) => check_rules(&config_dir.join("theshit/fix_rules"), &mut checks),
+        None => {
+            checks.push(Check::fail(
+                "Could not determine the config directory",
+                "Set XDG_CONFIG_HOME or HOME",
+            ));
+            false
+        }
+    };
+    checks.push(check_python(fix::python_version(), has_python_rules));
+
+    for check in &checks {
+        println!("{check}");
+    }
+    checks.iter().all(|check| check.verdict != Verdict::Fail)
+}
+
+fn check_shell(shell_name: Option<&str>, checks: &mut Vec<Check>) -> Option<Shell> {
+    let detected = match shell_name {
+        Some(name) => match Shell::from_str(name) {
+            Ok(shell) => Some((shell, "from --shell")),
+            Err(_) => {
+                checks.push(Check::fail(
+                    format!("Unknown shell '{name}'"),
+                    "Pass one of bash, zsh, fish, nu, powershell, xonsh, elvish or tcsh",
+                ));
+                return None;
+            }
+        },
+        None => shells::get_current_shell_by_env()
+            .map(|shell| (shell, "from SH_SHELL"))
+            .or_else(|| {
+                shells::get_current_shell_by_process()
+                    .map(|shell| (shell, "from the parent processes"))
+            }),
+    };
+    match detected {
+        Some((shell, source)) => {
+            checks.push(Check::pass(format!("Shell: {shell:?} ({source})")));
+            Some(shell)
+        }
+        None => {
+            checks.push(Check::fail(
+                "Could not determine the current shell",
+                "Pass it explicitly, like `theshit --shell bash doctor`",
+            ));
+            None
+        }
+    }
+}
+
+fn check_setup(shell: &Shell) -> Check {
+    let hint = format!("Run `theshit setup` in {shell:?} and restart the shell");
+    // A dry run of the removal finds the setup without touching the config
+    let removals = match shell.remove_setup(true) {
+        Ok(removals) => removals,
+        Err(e) => return Check::fail(format!("Failed to read the shell configuration: {e}"), hint),
+    };
+    let Some(removal) = removals.first() else {
+        return Check::warn("The alias is not set up in the shell configuration", hint);
+    };
+    let setup = setup_text(&removals);
+    match std::env::current_exe() {
+        Ok(program_path) if !setup.contains(&*program_path.to_string_lossy()) => Check::warn(
+            format!(
+                "The alias in {} runs another theshit executable than {}",
+                removal.path.display(),
+                program_path.display()
+            ),
+            hint,
+        ),
+        _ => Check::pass(format!("Alias set up in {}", removal.path.display())),
+    }
+}
+
+/// Lines of the config, and whole files like the one Nushell sources.
+fn setup_text(removals: &[Removal]) -> String {
+    removals
+        .iter()
+        .map(|removal| match &removal.line {
+            Some(line) => line.clone(),
+            None => fs::read_to_string(&removal.path).unwrap_or_default(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `theshit fix` reads the failed command from `SH_PREV_CMD`, which only the alias sets.
+fn check_previous_command(is_set: bool) -> Check {
+    if is_set {
+        Check::pass("SH_PREV_CMD is set, running through the alias")
+    } else {
+        Check::warn(
+            "SH_PREV_CMD is not set, running outside the alias",
+            "`theshit fix` only works when the alias runs it, use the alias instead of calling it directly",
+        )
+    }
+}
+
+/// Checks the rules directory and returns whether it has Python rules.
+fn check_rules(rules_dir: &Path, checks: &mut Vec<Check>) -> bool {
+    let setup_hint = "Run `theshit setup` to create the default rules";
+    let active_dir = rules_dir.join("active");
+    if !active_dir.is_dir() {
+        checks.push(Check::fail(
+            format!("Rules directory {} is missing", active_dir.display()),
+            setup_hint,
+        ));
+        return false;
+    }
+    checks.push(Check::pass(format!(
+        "Rules directory: {}",
+        rules_dir.display()
+    )));
+    if !rules_dir.join("additional").is_dir() {
+        checks.push(Check::warn(
+            format!(
+                "Directory {} for disabled rules is missing",
+                rules_dir.join("additional").display()
+            ),
+            setup_hint,
+        ));
+    }
+    match fs::read_to_string(rules_dir.join(misc::APPLIED_RULES_FILE)) {
+        Ok(content) if content.lines().next() == Some(env!("CARGO_PKG_VERSION")) => {}
+        Ok(content) => checks.push(Check::warn(
+            format!(
+                "Default rules are from theshit {}, this is {}",
+                content.lines().next().unwrap_or("unknown"),
+                env!("CARGO_PKG_VERSION")
+            ),
+            "Run `theshit setup` to add the new default rules",
+        )),
+        Err(_) => checks.push(Check::warn(
+            "Unknown version of the default rules",
+            "Run `theshit setup` to add the new default rules",
+        )),
+    }
+
+    let mut paths = match fs::read_dir(&active_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            checks.push(Check::fail(
+                format!("Failed to read {}: {e}", active_dir.display()),
+                "Check the permissions of the rules directory",
+            ));
+            return false;
+        }
+    };
+    paths.sort();
+    let mut has_python_rules = false;
+    for path in paths.iter().filter(|path| path.is_file()) {
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+        match extension.as_ref() {
+            "bak" => continue,
+            "native" => {
+                let name = path.file_stem().unwrap_or_default().to_string_lossy();
+                if !fix::is_native_rule(&name) {
+                    checks.push(Check::warn(
+                        format!("Unknown native rule '{name}'"),
+                        format!(
+                            "Remove {} or check the name against the built-in rules",
+                            path.display()
+                        ),
+                    ));
+                }
+                continue;
+            }
+            "py" => has_python_rules = true,
+            _ => {}
+        }
+        if let Err(e) = fix::check_security(path) {
+            checks.push(Check::fail(
+                e,
+                format!("Run `chown $USER {0} && chmod go-w {0}`", path.display()),
+            ));
+        }
+    }
+    has_python_rules
+}
+
+fn check_python(version: Result<String, String>, has_python_rules: bool) -> Check {
+    match version {
+        Ok(version) => Check::pass(format!("Python: {version}")),
+        Err(e) if has_python_rules => Check::fail(
+            format!("Python rules can't run: {e}"),
+            "Install Python 3, or a theshit build with the python feature",
+        ),
+        Err(e) => Check::pass(format!("Python not available, no Python rules: {e}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn create_rules_dir(rules: &[&str]) -> tempfile::TempDir {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let active_dir = temp_dir.path().join("active");
+        fs::create_dir_all(&active_dir).unwrap();
+        fs::create_dir(temp_dir.path().join("additional")).unwrap();
+        fs::write(
+            temp_dir.path().join(misc::APPLIED_RULES_FILE),
+            format!("{}\n", env!("CARGO_PKG_VERSION")),
+        )
+        .unwrap();
+        for rule in rules {
+            let path = active_dir.join(rule);
+            fs::write(&path, "").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        }
+        temp_dir
+    }
+
+    fn verdicts(checks: &[Check]) -> Vec<&Verdict> {
+        checks.iter().map(|check| &check.verdict).collect()
+    }
+
+    #[test]
+    fn test_check_rules_passes() {
+        let rules_dir = create_rules_dir(&["sudo.native", "rule.py", "old.native.bak"]);
+        let mut checks = Vec::new();
+        assert!(check_rules(rules_dir.path(), &mut checks));
+        assert_eq!(verdicts(&checks), vec![&Verdict::Pass]);
+    }
+
+    #[test]
+    fn test_check_rules_missing_dir() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut checks = Vec::new();
+        assert!(!check_rules(temp_dir.path(), &mut checks));
+        assert_eq!(verdicts(&checks), vec![&Verdict::Fail]);
+    }
+
+    #[test]
+    fn test_check_rules_unknown_native_rule() {
+        let rules_dir = create_rules_dir(&["sudoo.native"]);
+        let mut checks = Vec::new();
+        assert!(!check_rules(rules_dir.path(), &mut checks));
+        assert_eq!(verdicts(&checks), vec![&Verdict::Pass, &Verdict::Warn]);
+        assert!(checks[1].message.contains("sudoo"));
+    }
+
+    #[test]
+    fn test_check_rules_writable_rule() {
+        let rules_dir = create_rules_dir(&["rule.py"]);
+        let path = rules_dir.path().join("active/rule.py");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o666)).unwrap();
+        let mut checks = Vec::new();
+        check_rules(rules_dir.path(), &mut checks);
+        assert_eq!(verdicts(&checks), vec![&Verdict::Pass, &Verdict::Fail]);
+        assert!(checks[1].hint.as_ref().unwrap().contains("chmod go-w"));
+    }
+
+    #[test]
+    fn test_check_rules_outdated_default_rules() {
+        let rules_dir = create_rules_dir(&[]);
+        fs::write(rules_dir.path().join(misc::APPLIED_RULES_FILE), "0.0.1\n").unwrap();
+        fs::remove_dir(rules_dir.path().join("additional")).unwrap();
+        let mut checks = Vec::new();
+        check_rules(rules_dir.path(), &mut checks);
+        assert_eq!(
+            verdicts(&checks),
+            vec![&Verdict::Pass, &Verdict::Warn, &Verdict::Warn]
+        );
+        assert!(checks[2].message.contains("0.0.1"));
+    }
+
+    #[test]
+    fn test_check_python() {
+        let missing = || Err("not found".to_string());
+        assert_eq!(check_python(missing(), true).verdict, Verdict::Fail);
+        assert_eq!(check_python(missing(), false).verdict, Verdict::Pass);
+        assert_eq!(
+            check_python(Ok("Python 3.12.3".to_string()), true).verdict,
+            Verdict::Pass
+        );
+    }
+
+    #[test]
+    fn test_setup_text_reads_sourced_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let function_path = temp_dir.path().join("theshit.nu");
+        fs::write(
+            &function_path,
+            "def shit [...args] { ^'/usr/bin/theshit' fix }\n",
+        )
+        .unwrap();
+        let removals = [
+            Removal {
+                path: temp_dir.path().join("config.nu"),
+                line: Some(format!("source '{}'", function_path.display())),
+            },
+            Removal {
+                path: function_path,
+                line: None,
+            },
+        ];
+        assert!(setup_text(&removals).contains("/usr/bin/theshit"));
+    }
+
+    #[test]
+    fn test_check_previous_command() {
+        assert_eq!(check_previous_command(true).verdict, Verdict::Pass);
+        assert_eq!(check_previous_command(false).verdict, Verdict::Warn);
+    }
+
+    #[test]
+    fn test_check_shell() {
+        let mut checks = Vec::new();
+        assert!(matches!(
+            check_shell(Some("zsh"), &mut checks),
+            Some(Shell::Zsh)
+        ));
+        assert!(check_shell(Some("cmd"), &mut checks).is_none());
+        assert_eq!(verdicts(&checks), vec![&Verdict::Pass, &Verdict::Fail]);
+    }
+}
@@ -1,53 +1,280 @@
+mod exec;
+mod history;
+mod instant;
+mod protocol;
+mod pty;
+#[cfg(any(feature = "python", feature = "python-runtime"))]
 mod python;
 mod rust;
+mod security;
+mod stats;
 mod structs;
+mod syntax;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+pub use history::{History, now as history_now};
+pub use instant::run_session as run_instant_session;
+pub use security::check_security;
+pub use stats::Stats;
+pub use structs::{RerunOptions, RunMode};
 
 use crate::fix::rust::NativeRule;
-use crate::fix::structs::CommandOutput;
+use crate::fix::stats::Outcome;
+use crate::fix::structs::{CommandOutput, DEFAULT_PRIORITY, FixedCommand};
+use crate::misc;
+use crate::shells::{self, Shell};
 use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers, read};
 use crossterm::style::Stylize;
+use std::cmp::Reverse;
+use std::collections::HashSet;
 use std::io::{ErrorKind, Write};
-use std::path::PathBuf;
-use std::process::Command;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 use std::str::FromStr;
 use std::sync::mpsc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{fs, io, thread};
 use structs::RawModeGuard;
 
-pub fn fix_command(command: String, expand_command: String) -> io::Result<String> {
-    let command_output = match get_command_output(expand_command) {
-        Ok(output) => output,
-        Err(e) => match e.kind() {
-            ErrorKind::NotFound => CommandOutput::new(
-                "command not found".to_string(),
-                "command not found".to_string(),
-            ),
-            ErrorKind::PermissionDenied => CommandOutput::new(
-                "permission denied".to_string(),
-                "permission denied".to_string(),
-            ),
-            _ => {
-                eprintln!("{}: {}", "Error executing command".red(), e);
-                return Err(e);
-            }
-        },
-    };
-    let command_struct = structs::Command::new(command, command_output);
+#[derive(Default)]
+struct RuleSet {
+    native: Vec<NativeRule>,
+    python: Vec<PathBuf>,
+    exec: Vec<PathBuf>,
+    wasm: Vec<PathBuf>,
+}
+
+/// Finds the fixes for the command and lets the user choose one, none if they cancelled.
+pub fn fix_command(
+    command: String,
+    expand_command: String,
+    shell: &Shell,
+    options: RerunOptions,
+) -> io::Result<Option<String>> {
     let active_rules_dir = dirs::config_dir()
         .ok_or(ErrorKind::NotFound)?
         .join("theshit/fix_rules/active");
-    let mut fixed_commands: Vec<String> = vec![];
-    let mut python_rules: Vec<PathBuf> = vec![];
-    for rule in fs::read_dir(active_rules_dir)? {
-        let rule = rule?;
-        let path = rule.path();
-        if path
-            .file_name()
-            .unwrap_or_else(|| panic!("Can't get get file name for {}", path.display()))
-            .to_string_lossy()
-            == "__pycache__"
-        {
+    let mut fixed_commands = find_fixed_commands(
+        command.clone(),
+        expand_command,
+        shell,
+        options,
+        &active_rules_dir,
+    )?;
+    let measurements = stats::take_measurements();
+    let mut history = match History::open() {
+        Ok(history) => Some(history),
+        Err(e) => {
+            eprintln!("{}: {e}", "Failed to read the fix history".yellow());
+            None
+        }
+    };
+    if let Some(history) = &history {
+        rank_by_history(&mut fixed_commands, history, &command);
+    }
+    let chosen = choose_fixed_command(&fixed_commands);
+    if let Some(history) = &mut history {
+        let entry = history::Entry::new(
+            command,
+            fixed_commands
+                .iter()
+                .map(|fixed| fixed.command().to_string())
+                .collect(),
+            chosen.map(|index| {
+                let fixed = &fixed_commands[index];
+                (fixed.command().to_string(), fixed.rule().to_string())
+            }),
+        );
+        if let Err(e) = history.record(entry) {
+            eprintln!("{}: {e}", "Failed to save the fix history".yellow());
+        }
+    }
+    // Read and written once per run, which leaves a concurrent run less room to lose an update
+    let result = Stats::open().and_then(|mut stats| {
+        stats.add(&measurements);
+        if let Some(index) = chosen {
+            stats.accept(fixed_commands[index].rule());
+        }
+        stats.save()
+    });
+    if let Err(e) = result {
+        eprintln!("{}: {e}", "Failed to save the rule statistics".yellow());
+    }
+    Ok(chosen.map(|index| fixed_commands.swap_remove(index).into()))
+}
+
+/// Moves the fixes chosen before for this or a similar command to the front.
+/// The sort is stable, so fixes never chosen keep their order.
+fn rank_by_history(fixed_commands: &mut [FixedCommand], history: &History, command: &str) {
+    fixed_commands
+        .sort_by_cached_key(|fixed| Reverse(history.score(command, fixed.command(), fixed.rule())));
+}
+
+fn find_fixed_commands(
+    command: String,
+    expand_command: String,
+    shell: &Shell,
+    options: RerunOptions,
+    rules_dir: &Path,
+) -> io::Result<Vec<FixedCommand>> {
+    let RuleSet {
+        native: native_rules,
+        python: python_rules,
+        exec: exec_rules,
+        wasm: wasm_rules,
+    } = collect_rules(rules_dir)?;
+    let pipe_status = shells::get_previous_pipe_status();
+    let command_text = structs::Command::new(command.clone(), CommandOutput::default())
+        .with_pipe_status(pipe_status.clone());
+    // Rules that can't apply are dropped here, so the interpreter only starts when needed
+    #[cfg(any(feature = "python", feature = "python-runtime"))]
+    let (native_locale_python_rules, python_rules): (Vec<PathBuf>, Vec<PathBuf>) = python_rules
+        .into_iter()
+        .filter(|rule| python::is_applicable(&command_text, rule))
+        .partition(|rule| python::wants_native_locale(rule));
+    #[cfg(not(any(feature = "python", feature = "python-runtime")))]
+    let native_locale_python_rules: Vec<PathBuf> = Vec::new();
+    // The second run repeats whatever the command does, so it's opt-in,
+    // without it the rules asking for the user's locale get the `LANG=C` output too
+    let (python_rules, native_locale_python_rules) = if options.native_locale_rerun {
+        (python_rules, native_locale_python_rules)
+    } else {
+        (
+            [python_rules, native_locale_python_rules].concat(),
+            Vec::new(),
+        )
+    };
+    let (output_rules, text_rules): (Vec<NativeRule>, Vec<NativeRule>) = native_rules
+        .into_iter()
+        .partition(|rule| rule.requires_output());
+
+    // Phase one: rules that only look at the command text, the command isn't re-run
+    let fixed_commands = apply_native_rules(&command_text, text_rules);
+    if options.text_only
+        || !fixed_commands.is_empty()
+        || (output_rules.is_empty()
+            && python_rules.is_empty()
+            && native_locale_python_rules.is_empty()
+            && exec_rules.is_empty()
+            && wasm_rules.is_empty())
+    {
+        return Ok(fixed_commands);
+    }
+
+    // Phase two: re-run the command and evaluate the rules that need its output
+    let command_output = capture_command_output(&command, &expand_command, shell, options)?;
+    // Rules asking for the user's locale need a second run, it's skipped when there are none
+    let native_locale_result = if native_locale_python_rules.is_empty() {
+        Ok(Vec::new())
+    } else {
+        let native_options = RerunOptions {
+            native_locale: true,
+            ..options
+        };
+        let output = capture_command_output(&command, &expand_command, shell, native_options)?;
+        process_python_rules(
+            &structs::Command::new(command.clone(), output).with_pipe_status(pipe_status.clone()),
+            native_locale_python_rules,
+        )
+    };
+    let command_struct =
+        structs::Command::new(command, command_output).with_pipe_status(pipe_status);
+    // Python rules share one interpreter, so they run as a separate stage next to the native ones
+    let (native_fixed_commands, python_result, exec_fixed_commands, wasm_fixed_commands) =
+        thread::scope(|scope| {
+            let python_stage = scope.spawn(|| process_python_rules(&command_struct, python_rules));
+            let exec_stage = scope
+                .spawn(|| exec::process_exec_rules(&command_struct, shell.as_ref(), exec_rules));
+            let wasm_stage =
+                scope.spawn(|| process_wasm_rules(&command_struct, shell.as_ref(), wasm_rules));
+            let native_fixed_commands = apply_native_rules(&command_struct, output_rules);
+            (
+                native_fixed_commands,
+                python_stage.join().expect("Python rules stage panicked"),
+                exec_stage.join().expect("Executable rules stage panicked"),
+                wasm_stage.join().expect("WebAssembly rules stage panicked"),
+            )
+        });
+    let mut fixed_commands = native_fixed_commands;
+    for result in [python_result, native_locale_result] {
+        match result {
+            Ok(commands) => fixed_commands.extend(commands),
+            Err(e) => eprintln!("{}: {}", "Python rules processing failed".red(), e),
+        }
+    }
+    fixed_commands.extend(exec_fixed_commands);
+    fixed_commands.extend(wasm_fixed_commands);
+    // The sort is stable, so fixes with the same priority keep the rule order
+    fixed_commands.sort_by_key(FixedCommand::priority);
+    Ok(fixed_commands)
+}
+
+#[cfg(any(feature = "python", feature = "python-runtime"))]
+fn process_python_rules(
+    command: &structs::Command,
+    rules: Vec<PathBuf>,
+) -> Result<Vec<FixedCommand>, String> {
+    if rules.is_empty() {
+        return Ok(vec![]);
+    }
+    python::process_python_rules(command, rules)
+}
+
+#[cfg(not(any(feature = "python", feature = "python-runtime")))]
+fn process_python_rules(
+    _: &structs::Command,
+    _: Vec<PathBuf>,
+) -> Result<Vec<FixedCommand>, String> {
+    Ok(vec![])
+}
+
+/// Version of the Python interpreter the rules run with.
+#[cfg(any(feature = "python", feature = "python-runtime"))]
+pub fn python_version() -> Result<String, String> {
+    python::version()
+}
+
+#[cfg(not(any(feature = "python", feature = "python-runtime")))]
+pub fn python_version() -> Result<String, String> {
+    Err("theshit was built without Python support".to_string())
+}
+
+pub fn is_native_rule(name: &str) -> bool {
+    NativeRule::from_str(name).is_ok()
+}
+
+#[cfg(feature = "wasm")]
+fn process_wasm_rules(
+    command: &structs::Command,
+    shell: &str,
+    rules: Vec<PathBuf>,
+) -> Vec<FixedCommand> {
+    if rules.is_empty() {
+        return vec![];
+    }
+    wasm::process_wasm_rules(command, shell, rules)
+}
+
+#[cfg(not(feature = "wasm"))]
+fn process_wasm_rules(_: &structs::Command, _: &str, _: Vec<PathBuf>) -> Vec<FixedCommand> {
+    vec![]
+}
+
+fn collect_rules(rules_dir: &Path) -> io::Result<RuleSet> {
+    let mut rules = RuleSet::default();
+    // Sort by path so the order of suggestions doesn't depend on the filesystem
+    let mut paths = fs::read_dir(rules_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            continue;
+        }
+        if exec::is_exec_rule(&path) {
+            rules.exec.push(path);
             continue;
         }
         match path.extension() {
@@ -60,14 +287,8 @@ pub fn fix_command(command: String, expand_command: String) -> io::Result<String
                             continue;
                         }
                     };
-                    let native_rule =
-                        NativeRule::from_str(native_rule_name.to_string_lossy().as_ref());
-                    match native_rule {
-                        Ok(rule) => {
-                            if let Some(fixed) = rule.fix_native(&command_struct) {
-                                fixed_commands.push(fixed)
-                            }
-                        }
+                    match NativeRule::from_str(native_rule_name.to_string_lossy().as_ref()) {
+                        Ok(rule) => rules.native.push(rule),
                         Err(_) => {
                             eprintln!(
                                 "{}{}{}",
@@ -79,7 +300,24 @@ pub fn fix_command(command: String, expand_command: String) -> io::Result<String
                         }
                     }
                 }
-                "py" => python_rules.push(path),
+                #[cfg(feature = "wasm")]
+                "wasm" => rules.wasm.push(path),
+                #[cfg(not(feature = "wasm"))]
+                "wasm" => eprintln!(
+                    "{}{}{}",
+                    "WebAssembly rule '".yellow(),
+                    path.display(),
+                    "' isn't supported, theshit was built without WebAssembly support".yellow()
+                ),
+                #[cfg(any(feature = "python", feature = "python-runtime"))]
+                "py" => rules.python.push(path),
+                #[cfg(not(any(feature = "python", feature = "python-runtime")))]
+                "py" => eprintln!(
+                    "{}{}{}",
+                    "Python rule '".yellow(),
+                    path.display(),
+                    "' isn't supported, theshit was built without Python support".yellow()
+                ),
                 _ => {
                     eprintln!(
                         "{}{}{}",
@@ -94,20 +332,87 @@ pub fn fix_command(command: String, expand_command: String) -> io::Result<String
             }
         }
     }
-    if !python_rules.is_empty() {
-        match python::process_python_rules(&command_struct, python_rules) {
-            Ok(commands) => fixed_commands.extend(commands),
-            Err(e) => eprintln!("{}: {}", "Python rules processing failed".red(), e),
-        }
+    Ok(rules)
+}
+
+/// Evaluates the rules on a pool of scoped threads.
+/// Every thread gets a contiguous chunk of rules and the chunks are joined in order,
+/// so the result keeps the order of `rules` regardless of which thread finishes first.
+fn apply_native_rules(command: &structs::Command, rules: Vec<NativeRule>) -> Vec<FixedCommand> {
+    let workers = thread::available_parallelism()
+        .map(NonZeroUsize::get)
+        .unwrap_or(1)
+        .min(rules.len());
+    if workers <= 1 {
+        return rules
+            .into_iter()
+            .filter_map(|rule| fix_native(rule, command))
+            .collect();
+    }
+
+    thread::scope(|scope| {
+        rules
+            .chunks(rules.len().div_ceil(workers))
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .filter_map(|rule| fix_native(*rule, command))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("Native rule thread panicked"))
+            .collect()
+    })
+}
+
+fn fix_native(rule: NativeRule, command: &structs::Command) -> Option<FixedCommand> {
+    let started = Instant::now();
+    let fixed = rule.fix_native(command);
+    stats::measure(
+        rule.file_name(),
+        started.elapsed(),
+        Outcome::from_match(fixed.is_some()),
+    );
+    fixed.map(|fixed| FixedCommand::new(fixed, DEFAULT_PRIORITY, rule.file_name()))
+}
+
+fn capture_command_output(
+    command: &str,
+    expand_command: &str,
+    shell: &Shell,
+    options: RerunOptions,
+) -> io::Result<CommandOutput> {
+    // In instant mode the output was already recorded
+    if let Some(output) = instant::last_output(command) {
+        return Ok(output);
+    }
+    match get_command_output(command, expand_command, shell, options) {
+        Ok(output) => Ok(output),
+        Err(e) => match e.kind() {
+            ErrorKind::NotFound => Ok(CommandOutput::new(
+                "command not found".to_string(),
+                "command not found".to_string(),
+            )),
+            ErrorKind::PermissionDenied => Ok(CommandOutput::new(
+                "permission denied".to_string(),
+                "permission denied".to_string(),
+            )),
+            _ => {
+                eprintln!("{}: {}", "Error executing command".red(), e);
+                Err(e)
+            }
+        },
     }
-    Ok(choose_fixed_command(fixed_commands))
 }
 
 fn get_command_timeout(command_name: &str) -> Duration {
     // Get the base command name without path
     let base_command = command_name
         .split('/')
-        .last()
+        .next_back()
         .unwrap_or(command_name);
     
     match base_command {
@@ -133,34 +438,108 @@ fn get_command_timeout(command_name: &str) -> Duration {
     }
 }
 
-fn get_command_output(expand_command: String) -> io::Result<CommandOutput> {
-    let split_command = shell_words::split(&expand_command)
-        .map_err(|e| io::Error::other(format!("Failed to parse command: {e}")))?;
-    
+fn get_command_output(
+    command: &str,
+    expand_command: &str,
+    shell: &Shell,
+    options: RerunOptions,
+) -> io::Result<CommandOutput> {
+    let (command, timeout) = rerun_command(
+        command,
+        expand_command,
+        shell,
+        options,
+        &shell.get_functions(),
+        &shell.get_alias_definitions(),
+        shells::get_previous_cwd().as_deref(),
+    )?;
+    run_with_timeout(command, timeout, options.pty)
+}
+
+/// Builds the re-run of the command in the directory it ran in, and its timeout.
+fn rerun_command(
+    command: &str,
+    expand_command: &str,
+    shell: &Shell,
+    options: RerunOptions,
+    functions: &HashSet<String>,
+    alias_definitions: &str,
+    cwd: Option<&Path>,
+) -> io::Result<(Command, Duration)> {
+    let split_command = match options.run_mode {
+        RunMode::Spawn => shell_words::split(expand_command)
+            .map_err(|e| io::Error::other(format!("Failed to parse command: {e}")))?,
+        // The shell reports syntax errors itself, only the binary is needed here
+        RunMode::Shell => misc::split_command(expand_command),
+    };
+
     if split_command.is_empty() {
         return Err(io::Error::new(
             ErrorKind::InvalidInput,
-            "Empty command provided"
+            "Empty command provided",
         ));
     }
-    
+
     let timeout = get_command_timeout(&split_command[0]);
-    
-    let child = Command::new(&split_command[0])
-        .args(&split_command[1..])
-        .env("LANG", "C") // Set locale to C to avoid issues with rules that depend on locale
-        .env("LC_ALL", "C")
-        .spawn()?;
-    
+
+    let mut command = if functions.contains(&split_command[0]) {
+        // Shell functions only exist with the user's configuration loaded,
+        // an interactive shell would take over the terminal without the null stdin
+        let mut command = shell.run_command(command, true);
+        command.stdin(Stdio::null());
+        command
+    } else {
+        match options.run_mode {
+            RunMode::Shell => shell.run_command(
+                &shell.script_with_aliases(command, expand_command, alias_definitions),
+                false,
+            ),
+            RunMode::Spawn => {
+                let mut command = Command::new(&split_command[0]);
+                command.args(&split_command[1..]);
+                command
+            }
+        }
+    };
+    // The shell function's own variables would leak into the command otherwise
+    for variable in shells::INTEGRATION_VARIABLES {
+        command.env_remove(variable);
+    }
+    if let Some(cwd) = cwd {
+        command.current_dir(cwd);
+    }
+    if !options.native_locale {
+        command
+            .env("LANG", "C") // Set locale to C to avoid issues with rules that depend on locale
+            .env("LC_ALL", "C");
+    }
+    Ok((command, timeout))
+}
+
+fn run_with_timeout(
+    mut command: Command,
+    timeout: Duration,
+    pty: bool,
+) -> io::Result<CommandOutput> {
     // Create a channel to communicate between threads
     let (sender, receiver) = mpsc::channel();
-    
+
     // Spawn a thread to wait for the child process
-    let _handle = thread::spawn(move || {
-        let result = child.wait_with_output();
-        let _ = sender.send(result);
-    });
-    
+    if pty {
+        thread::spawn(move || {
+            let _ = sender.send(pty::output(command));
+        });
+    } else {
+        let child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        thread::spawn(move || {
+            let result = child.wait_with_output();
+            let _ = sender.send(result);
+        });
+    }
+
     // Wait for either the command to complete or timeout
     match receiver.recv_timeout(timeout) {
         Ok(Ok(output)) => Ok(CommandOutput::from(output)),
@@ -171,19 +550,17 @@ fn get_command_output(expand_command: String) -> io::Result<CommandOutput> {
             // We'll let the thread continue and return a timeout error
             Err(io::Error::new(
                 ErrorKind::TimedOut,
-                format!("Command timed out after {:?}", timeout)
+                format!("Command timed out after {:?}", timeout),
             ))
         }
         Err(mpsc::RecvTimeoutError::Disconnected) => {
-            Err(io::Error::new(
-                ErrorKind::Other,
-                "Command thread disconnected unexpectedly"
-            ))
+            Err(io::Error::other("Command thread disconnected unexpectedly"))
         }
     }
 }
 
-fn choose_fixed_command(mut fixed_commands: Vec<String>) -> String {
+/// Lets the user pick a fix and returns its index, none if they cancelled.
+fn choose_fixed_command(fixed_commands: &[FixedCommand]) -> Option<usize> {
     if fixed_commands.is_empty() {
         eprintln!(
             "{}: {}",
@@ -202,7 +579,7 @@ fn choose_fixed_command(mut fixed_commands: Vec<String>) -> String {
     err.write_all(
         format!(
             "{} [{}/{}/{}/{}]",
-            current_command,
+            current_command.command(),
             "enter".green(),
             "↑".cyan(),
             "↓".cyan(),
@@ -219,61 +596,61 @@ fn choose_fixed_command(mut fixed_commands: Vec<String>) -> String {
                 }) = event
                 {
                     match (code, modifiers) {
-                        (KeyCode::Up, _) => {
-                            if fixed_commands.len() > 1 {
-                                if current_index > 0 {
-                                    current_index -= 1;
-                                } else {
-                                    current_index = fixed_commands.len() - 1;
-                                }
-                                current_command = fixed_commands.get(current_index).unwrap();
-                                err.write_all(
-                                    format!(
-                                        "{} [{}/{}/{}/{}]",
-                                        current_command,
-                                        "enter".green(),
-                                        "↑".cyan(),
-                                        "↓".cyan(),
-                                        "Ctrl+C".red()
-                                    )
-                                    .as_bytes(),
-                                )
-                                .expect("Failed to write to stderr");
+                        (KeyCode::Up, _) if fixed_commands.len() > 1 => {
+                            if current_index > 0 {
+                                current_index -= 1;
+                            } else {
+                                current_index = fixed_commands.len() - 1;
                             }
-                        }
-                        (KeyCode::Down, _) => {
-                            if fixed_commands.len() > 1 {
-                                if current_index < fixed_commands.len() - 1 {
-                                    current_index += 1;
-                                } else {
-                                    current_index = 0;
-                                }
-                                current_command = fixed_commands.get(current_index).unwrap();
-                                err.write_all(
-                                    format!(
-                                        "{} [{}/{}/{}/{}]",
-                                        current_command,
-                                        "enter".green(),
-                                        "↑".cyan(),
-                                        "↓".cyan(),
-                                        "Ctrl+C".red()
-                                    )
-                                    .as_bytes(),
+                            current_command = fixed_commands.get(current_index).unwrap();
+                            err.write_all(
+                                format!(
+                                    "{} [{}/{}/{}/{}]",
+                                    current_command.command(),
+                                    "enter".green(),
+                                    "↑".cyan(),
+                                    "↓".cyan(),
+                                    "Ctrl+C".red()
                                 )
-                                .expect("Failed to write to stderr");
+                                .as_bytes(),
+                            )
+                            .expect("Failed to write to stderr");
+                        }
+                        (KeyCode::Down, _) if fixed_commands.len() > 1 => {
+                            if current_index < fixed_commands.len() - 1 {
+                                current_index += 1;
+                            } else {
+                                current_index = 0;
                             }
+                            current_command = fixed_commands.get(current_index).unwrap();
+                            err.write_all(
+                                format!(
+                                    "{} [{}/{}/{}/{}]",
+                                    current_command.command(),
+                                    "enter".green(),
+                                    "↑".cyan(),
+                                    "↓".cyan(),
+                                    "Ctrl+C".red()
+                                )
+                                .as_bytes(),
+                            )
+                            .expect("Failed to write to stderr");
                         }
                         (KeyCode::Enter, _) => {
                             drop(_raw_mode_guard);
                             eprintln!();
-                            eprintln!("{}: {}", "Selected command: ".green(), &current_command);
-                            return fixed_commands.remove(current_index);
+                            eprintln!(
+                                "{}: {}",
+                                "Selected command: ".green(),
+                                current_command.command()
+                            );
+                            return Some(current_index);
                         }
                         (KeyCode::Char('c'), KeyModifiers::CONTROL) => {
                             drop(_raw_mode_guard);
                             eprintln!();
                             eprintln!("{}: {}", "Exiting...".yellow(), "User interrupted".red());
-                            std::process::exit(1);
+                            return None;
                         }
                         _ => {}
                     }
@@ -321,7 +698,7 @@ mod tests {
     
     #[test]
     fn test_get_command_output_empty_command() {
-        let result = get_command_output("".to_string());
+        let result = get_command_output("", "", &Shell::Bash, RerunOptions::default());
         assert!(result.is_err());
         let err = result.err().unwrap();
         assert_eq!(err.kind(), ErrorKind::InvalidInput);
@@ -329,10 +706,344 @@ mod tests {
     
     #[test]
     fn test_get_command_output_nonexistent_command() {
-        let result = get_command_output("nonexistent_command_12345".to_string());
+        let result = get_command_output(
+            "nonexistent_command_12345",
+            "nonexistent_command_12345",
+            &Shell::Bash,
+            RerunOptions {
+                run_mode: RunMode::Spawn,
+                ..Default::default()
+            },
+        );
         assert!(result.is_err());
         // Note: The exact error type may vary between systems
         let err = result.err().unwrap();
         assert!(matches!(err.kind(), ErrorKind::NotFound));
     }
+
+    #[test]
+    fn test_get_command_output_runs_builtins_through_shell() {
+        let result = get_command_output(
+            "cd /nonexistent_dir_12345",
+            "cd /nonexistent_dir_12345",
+            &Shell::Bash,
+            RerunOptions::default(),
+        );
+        let output = result.unwrap();
+        assert_eq!(output.exit_code(), Some(1));
+        assert!(output.stderr().contains("/nonexistent_dir_12345"));
+    }
+
+    #[test]
+    fn test_get_command_output_runs_pipes_through_shell() {
+        let result = get_command_output(
+            "echo shit | tr a-z A-Z",
+            "echo shit | tr a-z A-Z",
+            &Shell::Bash,
+            RerunOptions::default(),
+        );
+        assert_eq!(result.unwrap().stdout(), "SHIT\n");
+    }
+
+    #[test]
+    fn test_rerun_command_uses_aliases_in_shell() {
+        let (command, timeout) = rerun_command(
+            "echo shit | shout",
+            "echo shit | shout",
+            &Shell::Bash,
+            RerunOptions::default(),
+            &HashSet::new(),
+            "shopt -s expand_aliases\nalias shout='tr a-z A-Z'",
+            None,
+        )
+        .unwrap();
+        let output = run_with_timeout(command, timeout, false).unwrap();
+        assert_eq!(output.stdout(), "SHIT\n");
+    }
+
+    #[test]
+    fn test_get_command_output_with_pty() {
+        let command = "test -t 1 && printf '\\033[31mtty\\033[0m'";
+        let result = get_command_output(
+            command,
+            command,
+            &Shell::Bash,
+            RerunOptions {
+                pty: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(result.unwrap().stdout(), "tty");
+    }
+
+    #[test]
+    fn test_rerun_command_restores_environment() {
+        let cwd = tempfile::tempdir().unwrap();
+        let (command, timeout) = rerun_command(
+            "pwd",
+            "pwd",
+            &Shell::Bash,
+            RerunOptions::default(),
+            &HashSet::new(),
+            "",
+            Some(cwd.path()),
+        )
+        .unwrap();
+        assert!(
+            command
+                .get_envs()
+                .any(|(name, value)| name == "SH_PREV_CMD" && value.is_none())
+        );
+        let output = run_with_timeout(command, timeout, false).unwrap();
+        assert_eq!(output.stdout(), format!("{}\n", cwd.path().display()));
+    }
+
+    #[test]
+    fn test_get_command_output_locale() {
+        let command = "echo $LC_ALL";
+        let result = get_command_output(command, command, &Shell::Bash, RerunOptions::default());
+        assert_eq!(result.unwrap().stdout(), "C\n");
+        let result = get_command_output(
+            command,
+            command,
+            &Shell::Bash,
+            RerunOptions {
+                native_locale: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            result.unwrap().stdout(),
+            format!("{}\n", std::env::var("LC_ALL").unwrap_or_default())
+        );
+    }
+
+    #[test]
+    fn test_rerun_command_runs_functions_through_shell() {
+        let functions = HashSet::from(["nonexistent_function_12345".to_string()]);
+        let (mut command, timeout) = rerun_command(
+            "nonexistent_function_12345",
+            "nonexistent_function_12345",
+            &Shell::Bash,
+            RerunOptions::default(),
+            &functions,
+            "",
+            None,
+        )
+        .unwrap();
+        // An empty home keeps the user's rc files from slowing down the shell
+        let home_dir = tempfile::tempdir().unwrap();
+        command.env("HOME", home_dir.path());
+        // The shell reports the missing function itself instead of a failed spawn
+        let output = run_with_timeout(command, timeout, false).unwrap();
+        assert_eq!(output.exit_code(), Some(127));
+        assert!(output.stderr().contains("nonexistent_function_12345"));
+    }
+
+    #[test]
+    fn test_apply_native_rules_keeps_rule_order() {
+        let command = structs::Command::new(
+            "cs /root".to_string(),
+            CommandOutput::new(String::new(), "permission denied".to_string()),
+        );
+        let rules = vec![
+            NativeRule::Unsudo,
+            NativeRule::Sudo,
+            NativeRule::MkdirP,
+            NativeRule::ToCd,
+            NativeRule::CargoNoCommand,
+            NativeRule::Sudo,
+        ];
+        for _ in 0..10 {
+            assert_eq!(
+                commands(&apply_native_rules(&command, rules.clone())),
+                vec!["sudo cs /root", "cd /root", "sudo cs /root"]
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(any(feature = "python", feature = "python-runtime"))]
+    fn test_collect_rules_sorted() {
+        let rules_dir = create_rules_dir(&["unsudo.native", "b.py", "sudo.native", "a.py"]);
+        let rules = collect_rules(rules_dir.path()).unwrap();
+        assert!(matches!(
+            rules.native.as_slice(),
+            [NativeRule::Sudo, NativeRule::Unsudo]
+        ));
+        assert_eq!(
+            rules.python,
+            vec![rules_dir.path().join("a.py"), rules_dir.path().join("b.py")]
+        );
+    }
+
+    #[test]
+    fn test_collect_rules_skips_directories() {
+        let rules_dir = create_rules_dir(&["sudo.native"]);
+        fs::create_dir(rules_dir.path().join("__pycache__")).unwrap();
+        let rules = collect_rules(rules_dir.path()).unwrap();
+        assert_eq!(rules.native.len(), 1);
+        assert!(rules.python.is_empty());
+    }
+
+    fn commands(fixed_commands: &[FixedCommand]) -> Vec<&str> {
+        fixed_commands.iter().map(FixedCommand::command).collect()
+    }
+
+    fn create_rules_dir(rules: &[&str]) -> tempfile::TempDir {
+        let rules_dir = tempfile::tempdir().unwrap();
+        for rule in rules {
+            fs::write(rules_dir.path().join(rule), "").unwrap();
+        }
+        rules_dir
+    }
+
+    #[test]
+    fn test_find_fixed_commands_without_rerun() {
+        let rules_dir = create_rules_dir(&["to_cd.native", "sudo.native"]);
+        // An empty expanded command can't be re-run, so any attempt would fail
+        let result = find_fixed_commands(
+            "cs /tmp".to_string(),
+            "".to_string(),
+            &Shell::Bash,
+            RerunOptions::default(),
+            rules_dir.path(),
+        );
+        let result = result.unwrap();
+        assert_eq!(commands(&result), vec!["cd /tmp"]);
+        assert_eq!(result[0].rule(), "to_cd.native");
+    }
+
+    #[test]
+    fn test_find_fixed_commands_with_rerun() {
+        let rules_dir = create_rules_dir(&["to_cd.native", "mkdir_p.native"]);
+        let result = find_fixed_commands(
+            "mkdir /nonexistent_dir_12345/child".to_string(),
+            "mkdir /nonexistent_dir_12345/child".to_string(),
+            &Shell::Bash,
+            RerunOptions::default(),
+            rules_dir.path(),
+        );
+        assert_eq!(
+            commands(&result.unwrap()),
+            vec!["mkdir -p /nonexistent_dir_12345/child"]
+        );
+    }
+
+    #[test]
+    fn test_find_fixed_commands_orders_by_priority() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let rules_dir = create_rules_dir(&["mkdir_p.native"]);
+        let exec_rule = rules_dir.path().join("mkdir.exec");
+        fs::write(
+            &exec_rule,
+            "#!/bin/sh\necho '{\"matched\": true, \"fixes\": [\"mkdir --parents a/b\"], \"priority\": 10}'\n",
+        )
+        .unwrap();
+        fs::set_permissions(&exec_rule, fs::Permissions::from_mode(0o755)).unwrap();
+        let result = find_fixed_commands(
+            "mkdir /nonexistent_dir_12345/child".to_string(),
+            "mkdir /nonexistent_dir_12345/child".to_string(),
+            &Shell::Bash,
+            RerunOptions::default(),
+            rules_dir.path(),
+        );
+        assert_eq!(
+            commands(&result.unwrap()),
+            vec![
+                "mkdir --parents a/b",
+                "mkdir -p /nonexistent_dir_12345/child"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_fixed_commands_skips_rerun_without_output_rules() {
+        let rules_dir = create_rules_dir(&["to_cd.native"]);
+        let result = find_fixed_commands(
+            "ls".to_string(),
+            "".to_string(),
+            &Shell::Bash,
+            RerunOptions::default(),
+            rules_dir.path(),
+        );
+        assert!(result.unwrap().is_empty());
+    }
+
+    #[cfg(any(feature = "python", feature = "python-runtime"))]
+    #[test]
+    fn test_find_fixed_commands_native_locale_rerun() {
+        let rules_dir = tempfile::tempdir().unwrap();
+        fs::write(
+            rules_dir.path().join("localized.py"),
+            "native_locale = True\n\n\
+             def match(command, stdout, stderr):\n    return True\n\n\
+             def fix(command, stdout, stderr):\n    return 'true'\n",
+        )
+        .unwrap();
+        let runs = rules_dir.path().join("runs");
+        let command = format!("echo run >> {}", runs.display());
+        for (native_locale_rerun, expected_runs) in [(false, 1), (true, 2)] {
+            fs::write(&runs, "").unwrap();
+            let result = find_fixed_commands(
+                command.clone(),
+                command.clone(),
+                &Shell::Bash,
+                RerunOptions {
+                    native_locale_rerun,
+                    ..Default::default()
+                },
+                rules_dir.path(),
+            );
+            assert_eq!(result.unwrap().len(), 1);
+            assert_eq!(
+                fs::read_to_string(&runs).unwrap().lines().count(),
+                expected_runs
+            );
+        }
+    }
+
+    #[test]
+    fn test_find_fixed_commands_text_only() {
+        let rules_dir = create_rules_dir(&["mkdir_p.native"]);
+        let directory = tempfile::tempdir().unwrap();
+        let created = directory.path().join("created");
+        let command = format!("mkdir {}", created.display());
+        let result = find_fixed_commands(
+            command.clone(),
+            command,
+            &Shell::Bash,
+            RerunOptions {
+                text_only: true,
+                ..Default::default()
+            },
+            rules_dir.path(),
+        );
+        assert!(result.unwrap().is_empty());
+        assert!(!created.exists());
+    }
+
+    #[test]
+    fn test_rank_by_history() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut history = History::load(temp_dir.path().join("history.jsonl")).unwrap();
+        history
+            .record(history::Entry::new(
+                "gti status".to_string(),
+                vec!["git status".to_string(), "gti --status".to_string()],
+                Some(("gti --status".to_string(), "flags.py".to_string())),
+            ))
+            .unwrap();
+        let mut fixed_commands = vec![
+            FixedCommand::new("git status".to_string(), 10, "typo.py".to_string()),
+            FixedCommand::new("git stash".to_string(), 20, "typo.py".to_string()),
+            FixedCommand::new("gti --status".to_string(), 30, "flags.py".to_string()),
+        ];
+        rank_by_history(&mut fixed_commands, &history, "gti status");
+        assert_eq!(
+            commands(&fixed_commands),
+            vec!["gti --status", "git status", "git stash"]
+        );
+    }
 }
@@ -0,0 +1,220 @@
+use super::protocol::{RuleInput, RuleOutput};
+use super::security::check_security;
+use super::stats::{self, Outcome};
+use super::structs::{Command, FixedCommand, rule_name};
+use crossterm::style::Stylize;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::time::{Duration, Instant};
+use std::{fs, thread};
+
+static RULE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// `*.exec` files and executable files without an extension are run as rules.
+pub fn is_exec_rule(path: &Path) -> bool {
+    match path.extension() {
+        Some(extension) => extension == "exec",
+        None => fs::metadata(path)
+            .map(|metadata| metadata.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false),
+    }
+}
+
+pub fn process_exec_rules(
+    command: &Command,
+    shell: &str,
+    rule_paths: Vec<PathBuf>,
+) -> Vec<FixedCommand> {
+    let input = RuleInput::new(command, shell).to_json();
+
+    thread::scope(|scope| {
+        rule_paths
+            .iter()
+            .map(|rule_path| {
+                scope.spawn(|| {
+                    let started = Instant::now();
+                    let result = run_rule(rule_path, &input, RULE_TIMEOUT);
+                    (result, started.elapsed())
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .zip(&rule_paths)
+            .flat_map(|(handle, rule_path)| {
+                let (result, elapsed) = handle.join().expect("Executable rule thread panicked");
+                let rule = rule_name(rule_path);
+                match result {
+                    Ok(output) => {
+                        let fixed_commands = output.into_fixed_commands(&rule);
+                        stats::measure(
+                            rule,
+                            elapsed,
+                            Outcome::from_match(!fixed_commands.is_empty()),
+                        );
+                        fixed_commands
+                    }
+                    Err(e) => {
+                        stats::measure(rule, elapsed, Outcome::Failed);
+                        eprintln!(
+                            "{}{}{}{}",
+                            "Executable rule '".yellow(),
+                            rule_path.display(),
+                            "' failed: ".yellow(),
+                            e
+                        );
+                        vec![]
+                    }
+                }
+            })
+            .collect()
+    })
+}
+
+fn run_rule(rule_path: &Path, input: &[u8], timeout: Duration) -> Result<RuleOutput, String> {
+    check_security(rule_path)?;
+    // The rule leads its own process group, so the processes it forks are killed with it
+    // and none of them keeps the output pipe open
+    let mut child = process::Command::new(rule_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .process_group(0)
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    let process_group = child.id() as libc::pid_t;
+    let mut stdin = child.stdin.take().expect("Rule stdin is piped");
+    let mut stdout = child.stdout.take().expect("Rule stdout is piped");
+
+    let deadline = Instant::now() + timeout;
+    let (status, output) = thread::scope(|scope| {
+        // A rule may exit without reading its input, so a broken pipe isn't an error
+        scope.spawn(move || {
+            let _ = stdin.write_all(input);
+        });
+        let reader = scope.spawn(move || {
+            let mut output = vec![];
+            stdout.read_to_end(&mut output).map(|_| output)
+        });
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => {
+                    unsafe { libc::killpg(process_group, libc::SIGKILL) };
+                    break Ok(status);
+                }
+                Ok(None) if Instant::now() >= deadline => {
+                    unsafe { libc::killpg(process_group, libc::SIGKILL) };
+                    let _ = child.wait();
+                    break Err(format!("timed out after {timeout:?}"));
+                }
+                Ok(None) => thread::sleep(Duration::from_millis(5)),
+                Err(e) => break Err(e.to_string()),
+            }
+        };
+        (status, reader.join().expect("Rule output reader panicked"))
+    });
+
+    let status = status?;
+    if !status.success() {
+        return Err(format!("exited with {status}"));
+    }
+    RuleOutput::from_json(&output.map_err(|e| e.to_string())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fix::structs::{CommandOutput, DEFAULT_PRIORITY};
+
+    fn write_rule(dir: &Path, name: &str, script: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_is_exec_rule() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let exec_rule = write_rule(temp_dir.path(), "rule.exec", "");
+        let executable = write_rule(temp_dir.path(), "rule", "");
+        let plain_file = temp_dir.path().join("notes");
+        fs::write(&plain_file, "").unwrap();
+        assert!(is_exec_rule(&exec_rule));
+        assert!(is_exec_rule(&executable));
+        assert!(!is_exec_rule(&plain_file));
+        assert!(!is_exec_rule(Path::new("rule.py")));
+    }
+
+    #[test]
+    fn test_process_exec_rules() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let matching_rule = write_rule(
+            temp_dir.path(),
+            "matching.exec",
+            "#!/bin/sh\n\
+             grep -q '\"shell\":\"bash\"' || exit 1\n\
+             echo '{\"matched\": true, \"fixes\": [\"git push\", \"git pull\"], \"priority\": 10}'\n",
+        );
+        let other_rule = write_rule(
+            temp_dir.path(),
+            "other.exec",
+            "#!/bin/sh\necho '{\"matched\": false}'\n",
+        );
+        let command = Command::new("git psuh".to_string(), CommandOutput::default());
+        let fixed_commands = process_exec_rules(&command, "bash", vec![matching_rule, other_rule]);
+        assert_eq!(fixed_commands.len(), 2);
+        assert_eq!(fixed_commands[0].command(), "git push");
+        assert_eq!(fixed_commands[1].command(), "git pull");
+        assert_eq!(fixed_commands[0].priority(), 10);
+        assert_eq!(fixed_commands[1].rule(), "matching.exec");
+    }
+
+    #[test]
+    fn test_run_rule_default_priority() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let rule = write_rule(
+            temp_dir.path(),
+            "rule.exec",
+            "#!/bin/sh\necho '{\"matched\": true, \"fixes\": [\"ls\"]}'\n",
+        );
+        let fixed_commands = run_rule(&rule, b"{}", RULE_TIMEOUT)
+            .unwrap()
+            .into_fixed_commands("rule.exec");
+        assert_eq!(fixed_commands[0].priority(), DEFAULT_PRIORITY);
+    }
+
+    #[test]
+    fn test_run_rule_timeout() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        // Without `exec` the shell forks, and the child holds the output pipe too
+        let rule = write_rule(temp_dir.path(), "slow.exec", "#!/bin/sh\nsleep 5\n");
+        let started = Instant::now();
+        let result = run_rule(&rule, b"{}", Duration::from_millis(100));
+        assert!(result.unwrap_err().contains("timed out"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_run_rule_kills_background_processes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let rule = write_rule(
+            temp_dir.path(),
+            "background.exec",
+            "#!/bin/sh\nsleep 5 &\necho '{\"matched\": false}'\n",
+        );
+        let started = Instant::now();
+        assert!(run_rule(&rule, b"{}", RULE_TIMEOUT).is_ok());
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_run_rule_invalid_reply() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let rule = write_rule(temp_dir.path(), "broken.exec", "#!/bin/sh\necho oops\n");
+        let result = run_rule(&rule, b"{}", RULE_TIMEOUT);
+        assert!(result.unwrap_err().contains("invalid reply"));
+    }
+}
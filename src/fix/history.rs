@@ -0,0 +1,241 @@
+//! Fixes suggested and chosen before, kept so the fixes the user picks come first next time.
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAX_ENTRIES: usize = 1000;
+/// A fix chosen for the very same command counts more than one by the same rule for the same program.
+const SAME_COMMAND_BOOST: usize = 3;
+const SAME_PROGRAM_BOOST: usize = 1;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Entry {
+    pub timestamp: u64,
+    pub command: String,
+    pub suggestions: Vec<String>,
+    /// Fix the user chose, none if they cancelled.
+    pub chosen: Option<String>,
+    /// Rule that suggested the chosen fix.
+    pub rule: Option<String>,
+}
+
+impl Entry {
+    pub fn new(
+        command: String,
+        suggestions: Vec<String>,
+        chosen: Option<(String, String)>,
+    ) -> Self {
+        let (chosen, rule) = chosen.unzip();
+        Entry {
+            timestamp: now(),
+            command,
+            suggestions,
+            chosen,
+            rule,
+        }
+    }
+
+    /// How long ago the entry was recorded, like `5m ago`.
+    pub fn age(&self, now: u64) -> String {
+        let seconds = now.saturating_sub(self.timestamp);
+        match seconds {
+            0..60 => "just now".to_string(),
+            60..3600 => format!("{}m ago", seconds / 60),
+            3600..86400 => format!("{}h ago", seconds / 3600),
+            _ => format!("{}d ago", seconds / 86400),
+        }
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// The history file, one JSON entry per line, oldest first.
+pub struct History {
+    path: PathBuf,
+    entries: Vec<Entry>,
+}
+
+impl History {
+    pub fn open() -> io::Result<Self> {
+        let path = dirs::data_dir()
+            .ok_or(ErrorKind::NotFound)?
+            .join("theshit/history.jsonl");
+        Self::load(path)
+    }
+
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        // A damaged line only loses its own entry
+        let entries = content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        Ok(History { path, entries })
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// How much a fix should be preferred for the command, from the fixes chosen before.
+    pub fn score(&self, command: &str, fix: &str, rule: &str) -> usize {
+        let program = command.split_whitespace().next();
+        self.entries
+            .iter()
+            .map(|entry| {
+                if entry.command == command && entry.chosen.as_deref() == Some(fix) {
+                    SAME_COMMAND_BOOST
+                } else if entry.command.split_whitespace().next() == program
+                    && entry.rule.as_deref() == Some(rule)
+                {
+                    SAME_PROGRAM_BOOST
+                } else {
+                    0
+                }
+            })
+            .sum()
+    }
+
+    pub fn record(&mut self, entry: Entry) -> io::Result<()> {
+        self.entries.push(entry);
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.drain(..self.entries.len() - MAX_ENTRIES);
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Written aside and renamed, so a crash doesn't lose the whole history.
+        // The commands may hold secrets, only the user can read them
+        let temporary_path = self.path.with_extension("tmp");
+        let _ = fs::remove_file(&temporary_path);
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&temporary_path)?;
+        for entry in &self.entries {
+            serde_json::to_writer(&mut file, entry)?;
+            file.write_all(b"\n")?;
+        }
+        fs::rename(&temporary_path, &self.path)
+    }
+
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.entries.clear();
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn accepted(command: &str, fix: &str, rule: &str) -> Entry {
+        Entry::new(
+            command.to_string(),
+            vec![fix.to_string()],
+            Some((fix.to_string(), rule.to_string())),
+        )
+    }
+
+    #[test]
+    fn test_record_and_load() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("theshit/history.jsonl");
+        let mut history = History::load(path.clone()).unwrap();
+        assert!(history.entries().is_empty());
+        history
+            .record(accepted("gti status", "git status", "typo.py"))
+            .unwrap();
+        history
+            .record(Entry::new("sl".to_string(), vec!["ls".to_string()], None))
+            .unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let history = History::load(path).unwrap();
+        assert_eq!(history.entries().len(), 2);
+        assert_eq!(history.entries()[0].chosen.as_deref(), Some("git status"));
+        assert_eq!(history.entries()[0].rule.as_deref(), Some("typo.py"));
+        assert_eq!(history.entries()[1].chosen, None);
+    }
+
+    #[test]
+    fn test_load_skips_damaged_lines() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("history.jsonl");
+        let entry = serde_json::to_string(&accepted("sl", "ls", "typo.py")).unwrap();
+        fs::write(&path, format!("{{\"timestamp\n{entry}\n")).unwrap();
+        assert_eq!(History::load(path).unwrap().entries().len(), 1);
+    }
+
+    #[test]
+    fn test_record_keeps_last_entries() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut history = History::load(temp_dir.path().join("history.jsonl")).unwrap();
+        history.entries = (0..MAX_ENTRIES)
+            .map(|index| accepted(&index.to_string(), "ls", "typo.py"))
+            .collect();
+        history.record(accepted("last", "ls", "typo.py")).unwrap();
+        assert_eq!(history.entries().len(), MAX_ENTRIES);
+        assert_eq!(history.entries()[0].command, "1");
+        assert_eq!(history.entries()[MAX_ENTRIES - 1].command, "last");
+    }
+
+    #[test]
+    fn test_score() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut history = History::load(temp_dir.path().join("history.jsonl")).unwrap();
+        history.entries = vec![
+            accepted("gti status", "git status", "typo.py"),
+            accepted("gti push", "git push", "typo.py"),
+            Entry::new("gti log".to_string(), vec!["git log".to_string()], None),
+        ];
+        assert_eq!(
+            history.score("gti status", "git status", "typo.py"),
+            SAME_COMMAND_BOOST + SAME_PROGRAM_BOOST
+        );
+        assert_eq!(
+            history.score("gti log", "git log", "typo.py"),
+            2 * SAME_PROGRAM_BOOST
+        );
+        assert_eq!(history.score("gti log", "gti log", "other.py"), 0);
+        assert_eq!(history.score("ls", "ls -l", "typo.py"), 0);
+    }
+
+    #[test]
+    fn test_clear() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("history.jsonl");
+        let mut history = History::load(path.clone()).unwrap();
+        history.record(accepted("sl", "ls", "typo.py")).unwrap();
+        history.clear().unwrap();
+        assert!(!path.exists());
+        assert!(history.clear().is_ok());
+    }
+
+    #[test]
+    fn test_age() {
+        let entry = accepted("sl", "ls", "typo.py");
+        assert_eq!(entry.age(entry.timestamp + 5), "just now");
+        assert_eq!(entry.age(entry.timestamp + 300), "5m ago");
+        assert_eq!(entry.age(entry.timestamp + 7200), "2h ago");
+        assert_eq!(entry.age(entry.timestamp + 3 * 86400), "3d ago");
+    }
+}
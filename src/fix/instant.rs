@@ -0,0 +1,444 @@
+//! Instant mode: the interactive shell runs under a pseudo-terminal proxy that records
+//! the output of every command, so a failed command doesn't have to be re-run.
+//!
+//! The shell integration marks where a command starts and ends with private OSC sequences,
+//! which terminals ignore. The start marker carries the command line, the output between
+//! the markers goes with it into a ring buffer file.
+use super::pty;
+use super::structs::{CommandOutput, RawModeGuard};
+use crate::misc;
+use crate::shells::Shell;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::time::Duration;
+use std::{env, thread};
+
+pub const LOG_VARIABLE: &str = "SH_INSTANT_LOG";
+
+/// Markers are `ESC ] 6973 ; B ; <command> BEL` and `ESC ] 6973 ; E BEL`.
+const MARKER_PREFIX: &[u8] = b"\x1b]6973;";
+const MARKER_END: u8 = 0x07;
+/// Longest marker kept while waiting for its end, anything longer isn't a marker.
+const MAX_MARKER_BYTES: usize = 4096;
+
+const MAX_ENTRIES: usize = 16;
+const MAX_ENTRY_BYTES: usize = 64 * 1024;
+const RESIZE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Entry {
+    command: String,
+    output: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum Marker {
+    Begin(String),
+    End,
+}
+
+/// Collects the output between the markers into the ring buffer file.
+struct Recorder {
+    path: PathBuf,
+    entries: VecDeque<Entry>,
+    current: Option<(String, Vec<u8>)>,
+    pending: Vec<u8>,
+}
+
+impl Recorder {
+    fn new(path: PathBuf) -> Self {
+        Recorder {
+            path,
+            entries: VecDeque::new(),
+            current: None,
+            pending: Vec::new(),
+        }
+    }
+
+    fn feed(&mut self, data: &[u8]) -> io::Result<()> {
+        self.pending.extend_from_slice(data);
+        let mut start = 0;
+        loop {
+            match find_marker(&self.pending[start..]) {
+                Found::Marker(position, length, marker) => {
+                    let rest = self.pending.split_off(start + position + length);
+                    self.pending.truncate(start + position);
+                    self.record_pending();
+                    self.pending = rest;
+                    start = 0;
+                    match marker {
+                        Marker::Begin(command) => self.current = Some((command, Vec::new())),
+                        Marker::End => {
+                            if let Some((command, output)) = self.current.take() {
+                                self.push(command, &output)?;
+                            }
+                        }
+                    }
+                }
+                Found::NotMarker(position) => start += position + 1,
+                // The end may be the start of a marker split across reads
+                Found::Partial(position) => {
+                    let rest = self.pending.split_off(start + position);
+                    self.record_pending();
+                    self.pending = rest;
+                    return Ok(());
+                }
+                Found::Nothing => {
+                    self.record_pending();
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn record_pending(&mut self) {
+        if let Some((_, output)) = &mut self.current {
+            output.extend_from_slice(&self.pending);
+            if output.len() > MAX_ENTRY_BYTES {
+                output.drain(..output.len() - MAX_ENTRY_BYTES);
+            }
+        }
+        self.pending.clear();
+    }
+
+    fn push(&mut self, command: String, output: &[u8]) -> io::Result<()> {
+        let output =
+            misc::strip_ansi_escapes(&String::from_utf8_lossy(output)).replace("\r\n", "\n");
+        if self.entries.len() == MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry { command, output });
+        // Written aside and renamed, so a reader never sees half a file
+        // The output may hold secrets, only the user can read it
+        let temporary_path = self.path.with_extension("tmp");
+        let _ = fs::remove_file(&temporary_path);
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&temporary_path)?;
+        serde_json::to_writer(file, &self.entries)?;
+        fs::rename(&temporary_path, &self.path)
+    }
+}
+
+enum Found {
+    /// Position and length of the marker.
+    Marker(usize, usize, Marker),
+    /// Looked like a marker at the position, but isn't one.
+    NotMarker(usize),
+    /// A marker may start at the position, its end hasn't been read yet.
+    Partial(usize),
+    Nothing,
+}
+
+fn find_marker(data: &[u8]) -> Found {
+    let candidate = (0..data.len()).find(|&position| {
+        let rest = &data[position..];
+        let length = rest.len().min(MARKER_PREFIX.len());
+        rest[..length] == MARKER_PREFIX[..length]
+    });
+    let Some(position) = candidate else {
+        return Found::Nothing;
+    };
+    let rest = &data[position..];
+    let Some(length) = rest.iter().position(|&byte| byte == MARKER_END) else {
+        return if rest.len() < MAX_MARKER_BYTES {
+            Found::Partial(position)
+        } else {
+            Found::NotMarker(position)
+        };
+    };
+    if length < MARKER_PREFIX.len() {
+        return Found::NotMarker(position);
+    }
+    let marker = match &rest[MARKER_PREFIX.len()..length] {
+        b"E" => Marker::End,
+        b"B" => Marker::Begin(String::new()),
+        [b'B', b';', command @ ..] => {
+            Marker::Begin(normalize_command(&String::from_utf8_lossy(command)))
+        }
+        _ => return Found::NotMarker(position),
+    };
+    Found::Marker(position, length + 1, marker)
+}
+
+/// The shells print the command line in their own way, only the words are compared.
+fn normalize_command(command: &str) -> String {
+    command
+        .split(|c: char| c.is_whitespace() || c.is_control())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn log_path() -> io::Result<PathBuf> {
+    let directory = dirs::runtime_dir()
+        .unwrap_or_else(env::temp_dir)
+        .join("theshit");
+    create_private_dir(&directory)?;
+    Ok(directory.join(format!("instant-{}.json", process::id())))
+}
+
+/// Creates the directory for the user only. In a shared directory like `/tmp` another user
+/// may have created it first, so an existing one is only used if it is private to the user.
+fn create_private_dir(directory: &Path) -> io::Result<()> {
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(directory)?;
+    let metadata = fs::symlink_metadata(directory)?;
+    if !metadata.is_dir()
+        || metadata.uid() != unsafe { libc::getuid() }
+        || metadata.mode() & 0o777 != 0o700
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is not a private directory", directory.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Output of the last finished command of the instant mode session, if it was the command.
+pub fn last_output(command: &str) -> Option<CommandOutput> {
+    let path = env::var_os(LOG_VARIABLE)?;
+    read_output(Path::new(&path), command)
+}
+
+fn read_output(path: &Path, command: &str) -> Option<CommandOutput> {
+    let entry = read_last_entry(path)?;
+    // The command may not have been recorded, like one ignored by the shell history
+    (entry.command == normalize_command(command))
+        .then(|| CommandOutput::new(entry.output.clone(), entry.output))
+}
+
+fn read_last_entry(path: &Path) -> Option<Entry> {
+    let entries: Vec<Entry> = serde_json::from_slice(&fs::read(path).ok()?).ok()?;
+    entries.into_iter().next_back()
+}
+
+/// Runs the user's shell under the proxy until it exits and returns its exit code.
+pub fn run_session(shell: &Shell) -> io::Result<i32> {
+    if env::var_os(LOG_VARIABLE).is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "already inside an instant mode session",
+        ));
+    }
+    if !io::stdin().is_terminal() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "instant mode needs a terminal",
+        ));
+    }
+    let path = log_path()?;
+    let pty::Pty { master, slave } = pty::open_pty()?;
+    let mut command = Command::new(shell.program());
+    command
+        .env(LOG_VARIABLE, &path)
+        .stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave));
+    // The shell leads its own session with the pseudo-terminal as the controlling terminal,
+    // so job control and Ctrl-C work as usual
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()?;
+    drop(command);
+
+    let result = proxy(master, Recorder::new(path.clone()));
+    let status = child.wait()?;
+    let _ = fs::remove_file(&path);
+    result?;
+    Ok(status.code().unwrap_or(1))
+}
+
+fn proxy(mut master: File, mut recorder: Recorder) -> io::Result<()> {
+    let _guard = RawModeGuard::new();
+    let mut input = master.try_clone()?;
+    thread::spawn(move || io::copy(&mut io::stdin(), &mut input));
+    let resized = master.try_clone()?;
+    thread::spawn(move || {
+        let mut size = crossterm::terminal::size().ok();
+        loop {
+            thread::sleep(RESIZE_POLL_INTERVAL);
+            let current_size = crossterm::terminal::size().ok();
+            if current_size != size && pty::sync_size(&resized).is_err() {
+                break;
+            }
+            size = current_size;
+        }
+    });
+
+    let mut stdout = io::stdout();
+    let mut buffer = [0; 8192];
+    loop {
+        let read = match master.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            // The shell exited and closed the slave side
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        stdout.write_all(&buffer[..read])?;
+        stdout.flush()?;
+        // Losing the log must not break the terminal
+        let _ = recorder.feed(&buffer[..read]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    const END_MARKER: &[u8] = b"\x1b]6973;E\x07";
+
+    fn marked(command: &str, output: &str) -> Vec<u8> {
+        format!("\x1b]6973;B;{command}\x07{output}\x1b]6973;E\x07").into_bytes()
+    }
+
+    #[test]
+    fn test_recorder_records_marked_output() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("log.json");
+        let mut recorder = Recorder::new(path.clone());
+        recorder.feed(b"$ prompt ").unwrap();
+        recorder
+            .feed(&marked("make", "\x1b[31merror\x1b[0m: failed\r\n"))
+            .unwrap();
+        recorder.feed(b"$ next prompt").unwrap();
+        assert_eq!(
+            read_last_entry(&path).unwrap(),
+            Entry {
+                command: "make".to_string(),
+                output: "error: failed\n".to_string()
+            }
+        );
+        assert_eq!(fs::metadata(&path).unwrap().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_create_private_dir() {
+        let directory = tempfile::tempdir().unwrap();
+        let private = directory.path().join("theshit");
+        create_private_dir(&private).unwrap();
+        assert_eq!(fs::metadata(&private).unwrap().mode() & 0o777, 0o700);
+        assert!(create_private_dir(&private).is_ok());
+
+        let shared = directory.path().join("shared");
+        fs::DirBuilder::new().mode(0o777).create(&shared).unwrap();
+        fs::set_permissions(&shared, fs::Permissions::from_mode(0o777)).unwrap();
+        let error = create_private_dir(&shared).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn test_recorder_finds_split_markers() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("log.json");
+        let mut recorder = Recorder::new(path.clone());
+        for byte in marked("ls -l", "output") {
+            recorder.feed(&[byte]).unwrap();
+        }
+        let entry = read_last_entry(&path).unwrap();
+        assert_eq!(
+            (entry.command.as_str(), entry.output.as_str()),
+            ("ls -l", "output")
+        );
+    }
+
+    #[test]
+    fn test_recorder_ignores_end_without_begin() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("log.json");
+        let mut recorder = Recorder::new(path.clone());
+        recorder.feed(b"first prompt").unwrap();
+        recorder.feed(END_MARKER).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_recorder_keeps_last_entries() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("log.json");
+        let mut recorder = Recorder::new(path.clone());
+        for index in 0..MAX_ENTRIES + 4 {
+            recorder.feed(&marked("seq", &index.to_string())).unwrap();
+        }
+        let entries: Vec<Entry> = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert_eq!(entries[0].output, "4");
+        assert_eq!(
+            read_last_entry(&path).unwrap().output,
+            (MAX_ENTRIES + 3).to_string()
+        );
+    }
+
+    #[test]
+    fn test_recorder_keeps_end_of_long_output() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("log.json");
+        let mut recorder = Recorder::new(path.clone());
+        let output = format!("{}end", "x".repeat(MAX_ENTRY_BYTES));
+        recorder.feed(&marked("cat log", &output)).unwrap();
+        let entry = read_last_entry(&path).unwrap().output;
+        assert_eq!(entry.len(), MAX_ENTRY_BYTES);
+        assert!(entry.ends_with("end"));
+    }
+
+    #[test]
+    fn test_recorder_ignores_other_escape_sequences() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("log.json");
+        let mut recorder = Recorder::new(path.clone());
+        recorder
+            .feed(b"\x1b]6973;B;ls\x07\x1b]0;title\x07")
+            .unwrap();
+        recorder.feed(b"\x1b]6973;X\x07output").unwrap();
+        recorder.feed(END_MARKER).unwrap();
+        let entry = read_last_entry(&path).unwrap();
+        assert_eq!(
+            (entry.command.as_str(), entry.output.as_str()),
+            ("ls", "output")
+        );
+    }
+
+    #[test]
+    fn test_normalize_command() {
+        assert_eq!(
+            normalize_command("\t  git  push\n  origin "),
+            "git push origin"
+        );
+    }
+
+    #[test]
+    fn test_read_output() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("log.json");
+        fs::write(
+            &path,
+            r#"[{"command": "ls", "output": ""}, {"command": "gti status", "output": "bash: gti: command not found\n"}]"#,
+        )
+        .unwrap();
+        let output = read_output(&path, "  gti status").unwrap();
+        assert_eq!(output.stderr(), "bash: gti: command not found\n");
+        assert_eq!(output.stdout(), "bash: gti: command not found\n");
+        // The last entry belongs to another command, like one left out of the shell history
+        assert!(read_output(&path, "gti push").is_none());
+    }
+}
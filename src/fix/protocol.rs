@@ -0,0 +1,115 @@
+use super::structs::{Command, DEFAULT_PRIORITY, FixedCommand};
+use super::syntax::{CommandLine, Pipeline};
+use crate::shells;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::path::PathBuf;
+
+/// Document a non-native rule receives with the failed command.
+#[derive(Serialize)]
+pub struct RuleInput<'a> {
+    command: &'a str,
+    parts: &'a [String],
+    pipelines: Option<&'a [Pipeline]>,
+    stdout: &'a str,
+    stderr: &'a str,
+    exit_code: Option<i32>,
+    pipe_status: &'a [i32],
+    failing_command: Option<usize>,
+    cwd: Option<PathBuf>,
+    shell: &'a str,
+}
+
+impl<'a> RuleInput<'a> {
+    pub fn new(command: &'a Command, shell: &'a str) -> Self {
+        RuleInput {
+            command: command.command(),
+            parts: command.parts(),
+            pipelines: command.script().map(CommandLine::pipelines),
+            stdout: command.output().stdout(),
+            stderr: command.output().stderr(),
+            exit_code: command.output().exit_code(),
+            pipe_status: command.pipe_status(),
+            failing_command: command.failing_command(),
+            cwd: shells::get_previous_cwd().or_else(|| env::current_dir().ok()),
+            shell,
+        }
+    }
+
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("Failed to serialize rule input")
+    }
+}
+
+/// Document a non-native rule replies with.
+#[derive(Deserialize, Debug)]
+pub struct RuleOutput {
+    matched: bool,
+    #[serde(default)]
+    fixes: Vec<String>,
+    #[serde(default = "default_priority")]
+    priority: i32,
+}
+
+fn default_priority() -> i32 {
+    DEFAULT_PRIORITY
+}
+
+impl RuleOutput {
+    pub fn from_json(json: &[u8]) -> Result<Self, String> {
+        serde_json::from_slice(json).map_err(|e| format!("invalid reply: {e}"))
+    }
+
+    pub fn into_fixed_commands(self, rule: &str) -> Vec<FixedCommand> {
+        if !self.matched {
+            return vec![];
+        }
+        self.fixes
+            .into_iter()
+            .map(|fix| FixedCommand::new(fix, self.priority, rule.to_string()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fix::structs::CommandOutput;
+
+    #[test]
+    fn test_rule_input_to_json() {
+        let command = Command::new(
+            "git psuh".to_string(),
+            CommandOutput::new(String::new(), "not a git command".to_string()),
+        );
+        let json: serde_json::Value =
+            serde_json::from_slice(&RuleInput::new(&command, "zsh").to_json()).unwrap();
+        assert_eq!(json["command"], "git psuh");
+        assert_eq!(json["parts"], serde_json::json!(["git", "psuh"]));
+        assert_eq!(json["stderr"], "not a git command");
+        assert_eq!(json["exit_code"], serde_json::Value::Null);
+        assert_eq!(json["shell"], "zsh");
+        assert_eq!(
+            json["pipelines"],
+            serde_json::json!([{
+                "commands": [{
+                    "words": ["git", "psuh"],
+                    "redirections": [],
+                    "span": {"start": 0, "end": 8},
+                }],
+                "separator": null,
+            }])
+        );
+    }
+
+    #[test]
+    fn test_rule_output_not_matched() {
+        let output = RuleOutput::from_json(br#"{"matched": false, "fixes": ["ls"]}"#).unwrap();
+        assert!(output.into_fixed_commands("rule.exec").is_empty());
+    }
+
+    #[test]
+    fn test_rule_output_invalid() {
+        assert!(RuleOutput::from_json(b"oops").is_err());
+    }
+}
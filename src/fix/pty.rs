@@ -0,0 +1,132 @@
+//! Runs commands under pseudo-terminals, so tools print what users see in their terminal.
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::process::{Command, Output, Stdio};
+use std::{ptr, thread};
+
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+
+pub(super) struct Pty {
+    pub(super) master: File,
+    pub(super) slave: OwnedFd,
+}
+
+fn window_size() -> libc::winsize {
+    let (columns, rows) = crossterm::terminal::size().unwrap_or(DEFAULT_SIZE);
+    libc::winsize {
+        ws_row: rows,
+        ws_col: columns,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+/// Opens a pseudo-terminal as big as the current terminal.
+pub(super) fn open_pty() -> io::Result<Pty> {
+    let mut size = window_size();
+    let mut master = -1;
+    let mut slave = -1;
+    let result = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &raw mut size,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (master, slave) = unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+    Ok(Pty { master, slave })
+}
+
+/// Without the `\n` to `\r\n` translation the output reads the same as from a pipe.
+fn disable_newline_translation(pty: &Pty) {
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(pty.slave.as_raw_fd(), &mut termios) == 0 {
+            termios.c_oflag &= !libc::ONLCR;
+            libc::tcsetattr(pty.slave.as_raw_fd(), libc::TCSANOW, &termios);
+        }
+    }
+}
+
+/// Resizes the pseudo-terminal to the current terminal, the kernel tells its programs with SIGWINCH.
+pub(super) fn sync_size(master: &File) -> io::Result<()> {
+    let size = window_size();
+    if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &raw const size) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Reads until every copy of the slave side is closed, which Linux reports as EIO.
+fn read_to_end(mut master: File) -> io::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    match master.read_to_end(&mut buffer) {
+        Ok(_) => Ok(buffer),
+        Err(e) if e.raw_os_error() == Some(libc::EIO) => Ok(buffer),
+        Err(e) => Err(e),
+    }
+}
+
+/// Runs the command with stdout and stderr on two separate pseudo-terminals,
+/// so both look like a terminal and still don't get mixed.
+pub fn output(mut command: Command) -> io::Result<Output> {
+    let stdout_pty = open_pty()?;
+    let stderr_pty = open_pty()?;
+    disable_newline_translation(&stdout_pty);
+    disable_newline_translation(&stderr_pty);
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::from(stdout_pty.slave))
+        .stderr(Stdio::from(stderr_pty.slave))
+        .spawn()?;
+    // The command keeps the slave sides open, the readers would never see the end otherwise
+    drop(command);
+
+    let stdout_reader = thread::spawn(move || read_to_end(stdout_pty.master));
+    let stderr_reader = thread::spawn(move || read_to_end(stderr_pty.master));
+    let status = child.wait()?;
+    let join = |reader: thread::JoinHandle<io::Result<Vec<u8>>>| {
+        reader
+            .join()
+            .map_err(|_| io::Error::other("PTY reader panicked"))?
+    };
+    Ok(Output {
+        status,
+        stdout: join(stdout_reader)?,
+        stderr: join(stderr_reader)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn test_output_is_a_terminal() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "test -t 1 && echo tty; test -t 2 && echo tty-err >&2"]);
+        let output = output(command).unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "tty\n");
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "tty-err\n");
+    }
+
+    #[test]
+    fn test_output_keeps_exit_code() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "exit 3"]);
+        assert_eq!(output(command).unwrap().status.code(), Some(3));
+    }
+
+    #[test]
+    fn test_output_command_not_found() {
+        let command = Command::new("nonexistent_command_12345");
+        assert_eq!(output(command).unwrap_err().kind(), ErrorKind::NotFound);
+    }
+}
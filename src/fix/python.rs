@@ -1,108 +1,149 @@
-use super::structs::Command;
+#[cfg(feature = "python")]
+mod embedded;
+#[cfg(not(feature = "python"))]
+mod runtime;
+
+#[cfg(feature = "python")]
+use embedded as backend;
+#[cfg(not(feature = "python"))]
+use runtime as backend;
+
+use super::security::check_security;
+use super::structs::{Command, FixedCommand};
 use crossterm::style::Stylize;
-use pyo3::types::{PyAnyMethods, PyList, PyListMethods};
-use pyo3::{PyResult, Python};
+use regex::Regex;
 use std::fs;
-use std::os::unix::fs::{MetadataExt, PermissionsExt};
 use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
 
-fn check_security(path: &Path) -> Result<(), String> {
-    let metadata = fs::metadata(path).map_err(|e| e.to_string())?;
-    let file_uid = metadata.uid();
-    let current_uid = unsafe { libc::geteuid() };
+static APPLIES_TO_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^applies_to\s*=").unwrap());
+static APPLIES_TO_LIST_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)^applies_to\s*=\s*[\[(](.*)[\])]$").unwrap());
+static NATIVE_LOCALE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^native_locale\s*=\s*True\s*(#.*)?$").unwrap());
+static STRING_LITERAL_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#""([^"]*)"|'([^']*)'"#).unwrap());
 
-    if current_uid != file_uid {
-        return Err(format!(
-            "{} Running with UID {}, but file '{}' is owned by UID {}. Aborting to prevent privilege escalation.",
-            "SECURITY ERROR:".red().bold(),
-            current_uid,
-            path.display(),
-            file_uid
-        ));
+/// Reads the top-level `applies_to = ["git", ...]` declaration of a rule without starting
+/// the interpreter. Rules without it apply to every command, a declaration that isn't a
+/// plain list of strings is returned as the error.
+fn parse_applies_to(source: &str) -> Result<Option<Vec<String>>, String> {
+    let mut lines = source
+        .lines()
+        .skip_while(|line| !APPLIES_TO_REGEX.is_match(line));
+    let first_line = match lines.next() {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    // The list may span several lines, up to its closing bracket
+    let mut declaration = strip_comment(first_line).to_string();
+    for line in lines {
+        if STRING_LITERAL_REGEX
+            .replace_all(&declaration, "")
+            .contains([']', ')'])
+        {
+            break;
+        }
+        declaration.push(' ');
+        declaration.push_str(strip_comment(line));
     }
+    let captures = APPLIES_TO_LIST_REGEX
+        .captures(declaration.trim_end())
+        .ok_or_else(|| first_line.trim_end().to_string())?;
+    Ok(Some(
+        STRING_LITERAL_REGEX
+            .captures_iter(&captures[1])
+            .filter_map(|literal| literal.get(1).or(literal.get(2)))
+            .map(|literal| literal.as_str().to_string())
+            .collect(),
+    ))
+}
 
-    if metadata.permissions().mode() & 0o022 == 0 {
-        return Err(format!(
-            "{} Python rule '{}' is writable by non-owners. Aborting to prevent privilege escalation.",
-            "SECURITY ERROR:".red().bold(),
-            path.display()
-        ));
+/// The line without its `#` comment, a `#` inside a string literal is kept.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (index, char) in line.char_indices() {
+        match (quote, char) {
+            (None, '#') => return &line[..index],
+            (None, '"' | '\'') => quote = Some(char),
+            (Some(open), _) if open == char => quote = None,
+            _ => {}
+        }
     }
+    line
+}
 
-    Ok(())
+/// Rules written against translated messages declare `native_locale = True`
+/// to get the output in the user's locale instead of the C one.
+pub fn wants_native_locale(rule_path: &Path) -> bool {
+    fs::read_to_string(rule_path).is_ok_and(|source| {
+        source
+            .lines()
+            .any(|line| NATIVE_LOCALE_REGEX.is_match(line.trim_end()))
+    })
+}
+
+pub fn is_applicable(command: &Command, rule_path: &Path) -> bool {
+    let applies_to = match fs::read_to_string(rule_path) {
+        Ok(source) => parse_applies_to(&source),
+        // Let the rule fail properly when it is processed
+        Err(_) => return true,
+    };
+    let applies_to = match applies_to {
+        Ok(Some(applies_to)) => applies_to,
+        Ok(None) => return true,
+        Err(declaration) => {
+            eprintln!(
+                "{}{}{}{}{}",
+                "Can't read '".yellow(),
+                declaration,
+                "' in rule '".yellow(),
+                rule_path.display(),
+                "', it applies to every command".yellow()
+            );
+            return true;
+        }
+    };
+    let program = match command.parts().first() {
+        Some(program) => Path::new(program)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        None => return false,
+    };
+    applies_to.contains(&program)
+}
+
+pub fn version() -> Result<String, String> {
+    backend::version()
 }
 
 pub fn process_python_rules(
     command: &Command,
     rule_paths: Vec<PathBuf>,
-) -> Result<Vec<String>, String> {
+) -> Result<Vec<FixedCommand>, String> {
     let module_path = get_common_parent(&rule_paths)
         .ok_or("No common parent found for rule paths".to_string())?;
-    let mut fixed_commands: Vec<String> = vec![];
-    pyo3::prepare_freethreaded_python();
-    Python::with_gil(|py| -> PyResult<()> {
-        {
-            let raw_sys_path = py.import("sys")?.getattr("path")?;
-            let sys_path = raw_sys_path.downcast::<PyList>()?;
-            sys_path.insert(0, module_path.to_string_lossy())?;
-        }
-
-        for rule_path in rule_paths {
+    let modules: Vec<(PathBuf, String)> = rule_paths
+        .into_iter()
+        .filter_map(|rule_path| {
             if let Err(e) = check_security(&rule_path) {
                 eprintln!("{}", e);
-                continue;
+                return None;
             }
+            get_module_name(&module_path, &rule_path).map(|module_name| (rule_path, module_name))
+        })
+        .collect();
+    if modules.is_empty() {
+        return Ok(vec![]);
+    }
+    backend::process_modules(command, &module_path, modules)
+}
 
-            let module_name = match get_module_name(&module_path, &rule_path) {
-                Some(module_name) => module_name,
-                None => continue,
-            };
-            let module = match py.import(&module_name) {
-                Ok(module) => module,
-                Err(e) => {
-                    eprintln!(
-                        "{}{}{}",
-                        "Failed to import rule module '".yellow(),
-                        rule_path.display(),
-                        "': ".yellow(),
-                    );
-                    eprintln!("{e}");
-                    continue;
-                }
-            };
-            let match_func = module.getattr("match")?;
-            let fix_func = module.getattr("fix")?;
-            if match_func.is_callable() && fix_func.is_callable() {
-                if match_func
-                    .call1((
-                        command.command(),
-                        command.output().stdout(),
-                        command.output().stderr(),
-                    ))?
-                    .extract::<bool>()?
-                {
-                    let fixed_command: String = fix_func
-                        .call1((
-                            command.command(),
-                            command.output().stdout(),
-                            command.output().stderr(),
-                        ))?
-                        .extract()?;
-                    fixed_commands.push(fixed_command);
-                }
-            } else {
-                eprintln!(
-                    "{}{}{}",
-                    "Rule '".yellow(),
-                    rule_path.display(),
-                    "' is missing required functions (match, fix)".yellow()
-                );
-            }
-        }
-        Ok(())
-    })
-    .map_err(|err| format!("Failed to process Python rules: {err}"))?;
-    Ok(fixed_commands)
+/// Directory for the rules bytecode, so it doesn't end up next to the rules.
+fn pycache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("theshit/pycache"))
 }
 
 fn get_module_name(modules_dir_path: &Path, rule_path: &Path) -> Option<String> {
@@ -162,3 +203,166 @@ fn get_common_parent(paths: &[PathBuf]) -> Option<PathBuf> {
         Some(common.iter().collect())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fix::structs::CommandOutput;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn write_rule(path: &Path, source: &str, mode: u32) {
+        fs::write(path, source).unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    #[test]
+    fn test_parse_applies_to_list() {
+        let source =
+            "import re\n\napplies_to = [\"git\", 'hub']\n\ndef match(command, stdout, stderr):\n";
+        assert_eq!(
+            parse_applies_to(source),
+            Ok(Some(vec!["git".to_string(), "hub".to_string()]))
+        );
+    }
+
+    #[test]
+    fn test_parse_applies_to_tuple_with_comment() {
+        let source = "applies_to = (\"cargo\",)  # only cargo\n";
+        assert_eq!(
+            parse_applies_to(source),
+            Ok(Some(vec!["cargo".to_string()]))
+        );
+    }
+
+    #[test]
+    fn test_parse_applies_to_multiline() {
+        let source =
+            "applies_to = [\n    \"git\",  # the usual one\n    'h#b',\n]\n\ndef match():\n";
+        assert_eq!(
+            parse_applies_to(source),
+            Ok(Some(vec!["git".to_string(), "h#b".to_string()]))
+        );
+    }
+
+    #[test]
+    fn test_parse_applies_to_unreadable() {
+        let source =
+            "applies_to = PROGRAMS + ['git']\n\ndef match(command):\n    return f(command)\n";
+        assert_eq!(
+            parse_applies_to(source),
+            Err("applies_to = PROGRAMS + ['git']".to_string())
+        );
+        assert!(parse_applies_to("applies_to = [\n    'git',\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_applies_to_missing() {
+        let source = "def match(command, stdout, stderr):\n    applies_to = ['git']\n";
+        assert_eq!(parse_applies_to(source), Ok(None));
+    }
+
+    #[test]
+    fn test_is_applicable() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let filtered_rule = temp_dir.path().join("filtered.py");
+        let generic_rule = temp_dir.path().join("generic.py");
+        fs::write(&filtered_rule, "applies_to = ['git']\n").unwrap();
+        fs::write(&generic_rule, "def match(command, stdout, stderr):\n").unwrap();
+
+        let git_command = Command::new("/usr/bin/git psuh".to_string(), CommandOutput::default());
+        let ls_command = Command::new("ls -l".to_string(), CommandOutput::default());
+        assert!(is_applicable(&git_command, &filtered_rule));
+        assert!(!is_applicable(&ls_command, &filtered_rule));
+        assert!(is_applicable(&ls_command, &generic_rule));
+    }
+
+    #[test]
+    fn test_process_python_rules() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let matching_rule = temp_dir.path().join("theshit_test_matching.py");
+        let other_rule = temp_dir.path().join("theshit_test_other.py");
+        write_rule(
+            &matching_rule,
+            "def match(command, stdout, stderr):\n    return 'denied' in stderr\n\n\
+             def fix(command, stdout, stderr):\n    return 'sudo ' + command\n",
+            0o644,
+        );
+        write_rule(
+            &other_rule,
+            "def match(command, stdout, stderr):\n    return False\n\n\
+             def fix(command, stdout, stderr):\n    return command\n",
+            0o644,
+        );
+        let command = Command::new(
+            "ls /root".to_string(),
+            CommandOutput::new(String::new(), "permission denied".to_string()),
+        );
+        let result = process_python_rules(&command, vec![matching_rule, other_rule]).unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].command(), "sudo ls /root");
+        assert_eq!(result[0].rule(), "theshit_test_matching.py");
+    }
+
+    #[test]
+    fn test_process_python_rules_large_output() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let rule = temp_dir.path().join("theshit_test_large_output.py");
+        write_rule(
+            &rule,
+            "def match(command, stdout, stderr):\n    return len(stderr) > 1000000\n\n\
+             def fix(command, stdout, stderr):\n    return 'make -s'\n",
+            0o644,
+        );
+        // More than fits into a single environment variable
+        let command = Command::new(
+            "make".to_string(),
+            CommandOutput::new(String::new(), "warning\n".repeat(200_000)),
+        );
+        let result = process_python_rules(&command, vec![rule]).unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].command(), "make -s");
+    }
+
+    #[test]
+    fn test_process_python_rules_skips_failing_rule() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let failing_rule = temp_dir.path().join("theshit_test_failing.py");
+        let matching_rule = temp_dir.path().join("theshit_test_after_failing.py");
+        write_rule(
+            &failing_rule,
+            "def match(command, stdout, stderr):\n    raise ValueError('broken')\n\n\
+             def fix(command, stdout, stderr):\n    return command\n",
+            0o644,
+        );
+        write_rule(
+            &matching_rule,
+            "def match(command, stdout, stderr):\n    return True\n\n\
+             def fix(command, stdout, stderr):\n    return 'ls'\n",
+            0o644,
+        );
+        let command = Command::new("sl".to_string(), CommandOutput::default());
+        let result = process_python_rules(&command, vec![failing_rule, matching_rule]).unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].rule(), "theshit_test_after_failing.py");
+    }
+
+    #[test]
+    fn test_wants_native_locale() {
+        let dir = tempfile::tempdir().unwrap();
+        let native_rule = dir.path().join("native.py");
+        fs::write(
+            &native_rule,
+            "native_locale = True  # messages are translated\n",
+        )
+        .unwrap();
+        let c_rule = dir.path().join("c.py");
+        fs::write(
+            &c_rule,
+            "def match(command, stdout, stderr):\n    native_locale = True\n",
+        )
+        .unwrap();
+        assert!(wants_native_locale(&native_rule));
+        assert!(!wants_native_locale(&c_rule));
+        assert!(!wants_native_locale(&dir.path().join("missing.py")));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::fix::stats::{self, Outcome};
+use crate::fix::structs::{Command, DEFAULT_PRIORITY, FixedCommand, rule_name};
+use crossterm::style::Stylize;
+use pyo3::exceptions::PyTypeError;
+use pyo3::types::{PyAnyMethods, PyList, PyListMethods, PyModule};
+use pyo3::{Bound, PyResult, Python};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Version of the embedded interpreter, like `Python 3.12.3`.
+pub fn version() -> Result<String, String> {
+    pyo3::prepare_freethreaded_python();
+    Python::with_gil(|py| {
+        let version = py.version();
+        Ok(format!(
+            "Python {} (embedded)",
+            version.split_whitespace().next().unwrap_or(version)
+        ))
+    })
+}
+
+pub fn process_modules(
+    command: &Command,
+    module_path: &Path,
+    modules: Vec<(PathBuf, String)>,
+) -> Result<Vec<FixedCommand>, String> {
+    let mut fixed_commands: Vec<FixedCommand> = vec![];
+    pyo3::prepare_freethreaded_python();
+    Python::with_gil(|py| -> PyResult<()> {
+        {
+            let sys = py.import("sys")?;
+            match super::pycache_dir() {
+                Some(pycache_dir) => {
+                    sys.setattr("pycache_prefix", pycache_dir.to_string_lossy())?
+                }
+                None => sys.setattr("dont_write_bytecode", true)?,
+            }
+            let raw_sys_path = sys.getattr("path")?;
+            let sys_path = raw_sys_path.downcast::<PyList>()?;
+            sys_path.insert(0, module_path.to_string_lossy())?;
+        }
+
+        for (rule_path, module_name) in modules {
+            let rule = rule_name(&rule_path);
+            let started = Instant::now();
+            let module = match py.import(&module_name) {
+                Ok(module) => module,
+                Err(e) => {
+                    stats::measure(rule, started.elapsed(), Outcome::Failed);
+                    eprintln!(
+                        "{}{}{}",
+                        "Failed to import rule module '".yellow(),
+                        rule_path.display(),
+                        "': ".yellow(),
+                    );
+                    eprintln!("{e}");
+                    continue;
+                }
+            };
+            // A failing rule is skipped, the other rules still run
+            match apply_rule(&module, command) {
+                Ok(fixed_command) => {
+                    stats::measure(
+                        rule.clone(),
+                        started.elapsed(),
+                        Outcome::from_match(fixed_command.is_some()),
+                    );
+                    if let Some(fixed_command) = fixed_command {
+                        fixed_commands.push(FixedCommand::new(
+                            fixed_command,
+                            DEFAULT_PRIORITY,
+                            rule,
+                        ));
+                    }
+                }
+                Err(e) => {
+                    stats::measure(rule, started.elapsed(), Outcome::Failed);
+                    eprintln!(
+                        "{}{}{}",
+                        "Rule '".yellow(),
+                        rule_path.display(),
+                        "' failed: ".yellow(),
+                    );
+                    eprintln!("{e}");
+                }
+            }
+        }
+        Ok(())
+    })
+    .map_err(|err| format!("Failed to process Python rules: {err}"))?;
+    Ok(fixed_commands)
+}
+
+fn apply_rule(module: &Bound<PyModule>, command: &Command) -> PyResult<Option<String>> {
+    let functions = module
+        .getattr("match")
+        .ok()
+        .zip(module.getattr("fix").ok())
+        .filter(|(match_func, fix_func)| match_func.is_callable() && fix_func.is_callable());
+    let Some((match_func, fix_func)) = functions else {
+        return Err(PyTypeError::new_err(
+            "rule is missing required functions (match, fix)",
+        ));
+    };
+    let arguments = (
+        command.command(),
+        command.output().stdout(),
+        command.output().stderr(),
+    );
+    if !match_func.call1(arguments)?.extract::<bool>()? {
+        return Ok(None);
+    }
+    fix_func.call1(arguments)?.extract().map(Some)
+}
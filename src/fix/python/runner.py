@@ -0,0 +1,45 @@
+import importlib
+import json
+import sys
+import time
+import traceback
+
+rule_input = json.load(sys.stdin)
+command = rule_input["command"]
+stdout = rule_input["stdout"]
+stderr = rule_input["stderr"]
+
+
+# Every rule is reported as `module<TAB>status<TAB>microseconds<TAB>fix`, NUL-terminated
+def report(module_name, status, started, fixed_command=""):
+    elapsed = int((time.perf_counter() - started) * 1_000_000)
+    sys.stdout.write(f"{module_name}\t{status}\t{elapsed}\t{fixed_command}\0")
+
+
+sys.path.insert(0, sys.argv[1])
+for module_name in sys.argv[2:]:
+    started = time.perf_counter()
+    try:
+        module = importlib.import_module(module_name)
+    except Exception:
+        print(f"Failed to import rule module '{module_name}': ", file=sys.stderr)
+        traceback.print_exc()
+        report(module_name, "error", started)
+        continue
+    match_func = getattr(module, "match", None)
+    fix_func = getattr(module, "fix", None)
+    if not (callable(match_func) and callable(fix_func)):
+        print(
+            f"Rule '{module_name}' is missing required functions (match, fix)",
+            file=sys.stderr,
+        )
+        report(module_name, "error", started)
+        continue
+    try:
+        if match_func(command, stdout, stderr):
+            report(module_name, "match", started, fix_func(command, stdout, stderr))
+        else:
+            report(module_name, "miss", started)
+    except Exception:
+        traceback.print_exc()
+        report(module_name, "error", started)
@@ -0,0 +1,105 @@
+//! Python rules run by the `python3` on the `PATH`, for binaries built without `libpython`.
+//!
+//! This is a separate interpreter process running `runner.py`, not a dynamically loaded
+//! `libpython`: the rules get that interpreter's `sys.path` and packages, and each fix pays
+//! for starting it.
+use crate::fix::stats::{self, Outcome};
+use crate::fix::structs::{Command, DEFAULT_PRIORITY, FixedCommand, rule_name};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::thread;
+use std::time::Duration;
+
+static RUNNER: &str = include_str!("runner.py");
+static INTERPRETER: &str = "python3";
+
+/// Version of the `python3` on the `PATH`, like `Python 3.12.3`.
+pub fn version() -> Result<String, String> {
+    let output = process::Command::new(INTERPRETER)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => format!("Python interpreter '{INTERPRETER}' not found"),
+            _ => format!("Failed to run Python interpreter '{INTERPRETER}': {e}"),
+        })?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Runs the rules with the `python3` found at runtime instead of an embedded interpreter,
+/// so the binary doesn't depend on libpython.
+pub fn process_modules(
+    command: &Command,
+    module_path: &Path,
+    modules: Vec<(PathBuf, String)>,
+) -> Result<Vec<FixedCommand>, String> {
+    let mut interpreter = process::Command::new(INTERPRETER);
+    interpreter
+        .arg("-c")
+        .arg(RUNNER)
+        .arg(module_path)
+        .args(modules.iter().map(|(_, module_name)| module_name))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    match super::pycache_dir() {
+        Some(pycache_dir) => interpreter.env("PYTHONPYCACHEPREFIX", pycache_dir),
+        None => interpreter.env("PYTHONDONTWRITEBYTECODE", "1"),
+    };
+
+    let mut child = interpreter.spawn().map_err(|e| match e.kind() {
+        ErrorKind::NotFound => {
+            format!("Python interpreter '{INTERPRETER}' not found, Python rules aren't supported")
+        }
+        _ => format!("Failed to run Python interpreter '{INTERPRETER}': {e}"),
+    })?;
+    // The output can be larger than the environment allows, it goes through stdin.
+    // Written on its own thread, the runner may report before it has read everything
+    let input = serde_json::json!({
+        "command": command.command(),
+        "stdout": command.output().stdout(),
+        "stderr": command.output().stderr(),
+    })
+    .to_string();
+    let mut stdin = child.stdin.take().expect("Interpreter stdin is piped");
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Failed to run Python interpreter '{INTERPRETER}': {e}"))?;
+    let _ = writer.join();
+    if !output.status.success() {
+        return Err(format!(
+            "Python interpreter '{INTERPRETER}' exited with {}",
+            output.status
+        ));
+    }
+    let rule_names: HashMap<&str, String> = modules
+        .iter()
+        .map(|(rule_path, module_name)| (module_name.as_str(), rule_name(rule_path)))
+        .collect();
+    // Every rule is reported as `module<TAB>status<TAB>microseconds<TAB>fix`,
+    // only the fix may have tabs
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split_terminator('\0')
+        .filter_map(|report| {
+            let mut fields = report.splitn(4, '\t');
+            let rule = rule_names.get(fields.next()?)?.clone();
+            let outcome = match fields.next()? {
+                "match" => Outcome::Matched,
+                "miss" => Outcome::NotMatched,
+                _ => Outcome::Failed,
+            };
+            let elapsed = Duration::from_micros(fields.next()?.parse().ok()?);
+            stats::measure(rule.clone(), elapsed, outcome);
+            (outcome == Outcome::Matched).then(|| {
+                FixedCommand::new(
+                    fields.next().unwrap_or_default().to_string(),
+                    DEFAULT_PRIORITY,
+                    rule,
+                )
+            })
+        })
+        .collect())
+}
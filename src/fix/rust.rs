@@ -5,9 +5,9 @@ mod to_cd;
 mod unsudo;
 
 use super::structs::Command;
-use strum::EnumString;
+use strum::{AsRefStr, EnumString};
 
-#[derive(EnumString, Debug)]
+#[derive(EnumString, AsRefStr, Debug, Clone, Copy)]
 pub enum NativeRule {
     #[strum(serialize = "sudo")]
     Sudo,
@@ -34,6 +34,17 @@ impl NativeRule {
         }
     }
 
+    /// Name of the rule file, like `sudo.native`.
+    pub fn file_name(&self) -> String {
+        format!("{}.native", self.as_ref())
+    }
+
+    /// Whether the rule has to see the output of the re-run command.
+    /// Rules that only look at the command text are evaluated before the command is re-run.
+    pub fn requires_output(&self) -> bool {
+        !matches!(self, NativeRule::ToCd)
+    }
+
     fn match_and_fix(
         match_function: fn(&Command) -> bool,
         fix_function: fn(&Command) -> String,
@@ -118,6 +129,15 @@ mod tests {
         assert_eq!(result.unwrap(), "cd /some/directory");
     }
 
+    #[test]
+    fn test_requires_output() {
+        assert!(NativeRule::Sudo.requires_output());
+        assert!(NativeRule::Unsudo.requires_output());
+        assert!(NativeRule::MkdirP.requires_output());
+        assert!(NativeRule::CargoNoCommand.requires_output());
+        assert!(!NativeRule::ToCd.requires_output());
+    }
+
     #[test]
     fn test_fix_native_no_match() {
         let command = Command::new(
@@ -1,9 +1,19 @@
 use crate::fix::structs::Command;
+use crate::fix::syntax::SimpleCommand;
 use regex::Regex;
 
 pub fn is_match(command: &Command) -> bool {
-    command.parts().contains(&"mkdir".to_string())
-        && !command.parts().contains(&"-p".to_string())
+    let has_fixable_mkdir = match command.script() {
+        Some(script) => script
+            .simple_commands()
+            .enumerate()
+            .any(|(index, simple_command)| is_fixable(command, index, simple_command)),
+        None => {
+            command.parts().contains(&"mkdir".to_string())
+                && !command.parts().contains(&"-p".to_string())
+        }
+    };
+    has_fixable_mkdir
         && (command
             .output()
             .stdout()
@@ -14,7 +24,25 @@ pub fn is_match(command: &Command) -> bool {
                 .contains("No such file or directory"))
 }
 
+/// Only the mkdir segments of a chain are rewritten, just the failing one when it is known.
+fn is_fixable(command: &Command, index: usize, simple_command: &SimpleCommand) -> bool {
+    command
+        .failing_command()
+        .is_none_or(|failing| failing == index)
+        && simple_command.program() == Some("mkdir")
+        && !simple_command.words().iter().any(|word| word == "-p")
+}
+
 pub fn fix(command: &Command) -> String {
+    if let Some(script) = command.script() {
+        return script.rewrite(|index, simple_command, source| {
+            is_fixable(command, index, simple_command).then(|| {
+                // After the program word, which may be quoted or come after a redirection
+                let program_end = simple_command.word_spans()[0].end - simple_command.span().start;
+                format!("{} -p{}", &source[..program_end], &source[program_end..])
+            })
+        });
+    }
     Regex::new(r"\bmkdir (.*)")
         .unwrap()
         .replace(command.command(), "mkdir -p $1")
@@ -70,4 +98,54 @@ mod tests {
         );
         assert_eq!(fix(&command), "mkdir -p some_directory");
     }
+
+    #[test]
+    fn test_fix_chain() {
+        let command = Command::new(
+            "mkdir a/b && echo mkdir > log && mkdir -p c/d".to_string(),
+            CommandOutput::new(String::new(), "No such file or directory".to_string()),
+        );
+        assert_eq!(
+            fix(&command),
+            "mkdir -p a/b && echo mkdir > log && mkdir -p c/d"
+        );
+    }
+
+    #[test]
+    fn test_fix_chain_with_quoted_and_redirected_mkdir() {
+        let command = Command::new(
+            "2>err 'mkdir' a/b && echo mkdir".to_string(),
+            CommandOutput::new(String::new(), "No such file or directory".to_string()),
+        );
+        assert_eq!(fix(&command), "2>err 'mkdir' -p a/b && echo mkdir");
+    }
+
+    #[test]
+    fn test_fix_only_failing_segment() {
+        let command = Command::new(
+            "mkdir build && cd build && mkdir out/logs".to_string(),
+            CommandOutput::new(
+                String::new(),
+                "mkdir: cannot create directory 'out/logs': No such file or directory".to_string(),
+            ),
+        )
+        .with_pipe_status(vec![1]);
+        assert_eq!(
+            fix(&command),
+            "mkdir build && cd build && mkdir -p out/logs"
+        );
+    }
+
+    #[test]
+    fn test_is_match_other_failing_segment() {
+        let command = Command::new(
+            "mkdir a && ls missing".to_string(),
+            CommandOutput::new(
+                String::new(),
+                "ls: cannot access 'missing': No such file or directory".to_string(),
+            ),
+        )
+        .with_pipe_status(vec![2]);
+        assert!(!is_match(&command));
+    }
 }
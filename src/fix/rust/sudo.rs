@@ -31,10 +31,24 @@ static PATTERNS: &[&str] = &[
     "updatedb: can not open a temporary file",
 ];
 pub fn is_match(command: &Command) -> bool {
-    if !command.parts().is_empty()
-        && !command.parts().contains(&"&&".to_string())
-        && command.parts()[0] == "sudo"
-    {
+    let already_sudo = match command.script() {
+        // When the failing segment is known, only that one gets root
+        Some(script) if script.is_compound() && command.failing_command().is_some() => script
+            .simple_commands()
+            .nth(command.failing_command().unwrap_or_default())
+            .is_none_or(|simple_command| simple_command.program() == Some("sudo")),
+        Some(script) => {
+            script.simple_commands().next().is_some()
+                && script
+                    .simple_commands()
+                    .all(|simple_command| simple_command.program() == Some("sudo"))
+        }
+        None => command
+            .parts()
+            .first()
+            .is_some_and(|program| program == "sudo"),
+    };
+    if already_sudo {
         return false;
     }
 
@@ -49,9 +63,43 @@ pub fn is_match(command: &Command) -> bool {
 }
 
 pub fn fix(command: &Command) -> String {
-    if command.command().contains("&&") {
-        format!("sudo sh -c '{}'", command.command().replace("sudo", ""))
-    } else if command.command().contains('>') {
+    let has_redirections = match command.script() {
+        // When the failing segment is known, only that one gets root
+        Some(script) if script.is_compound() && command.failing_command().is_some() => {
+            let failing_command = command.failing_command();
+            return script.rewrite(|index, simple_command, source| {
+                if Some(index) != failing_command || simple_command.program() == Some("sudo") {
+                    None
+                } else if simple_command.redirections().is_empty() {
+                    Some(format!("sudo {source}"))
+                } else {
+                    Some(format!("sudo sh -c {}", shell_words::quote(source)))
+                }
+            });
+        }
+        // Inside `sudo sh -c` the commands are already run as root
+        Some(script) if script.is_compound() => {
+            let without_sudo = script.rewrite(|_, simple_command, source| {
+                if simple_command.program() != Some("sudo") {
+                    return None;
+                }
+                // The program word may be quoted or come after a redirection
+                let offset = simple_command.span().start;
+                let program = simple_command.word_spans()[0].clone();
+                Some(format!(
+                    "{}{}",
+                    &source[..program.start - offset],
+                    source[program.end - offset..].trim_start()
+                ))
+            });
+            return format!("sudo sh -c {}", shell_words::quote(&without_sudo));
+        }
+        Some(script) => script
+            .simple_commands()
+            .any(|simple_command| !simple_command.redirections().is_empty()),
+        None => command.command().contains('>'),
+    };
+    if has_redirections {
         format!("sudo sh -c \"{}\"", command.command().replace("\"", "\\\""))
     } else {
         format!("sudo {}", command.command())
@@ -133,4 +181,66 @@ mod tests {
             "sudo sh -c \"some_command > output.txt\""
         );
     }
+
+    #[test]
+    fn test_is_match_chain_with_sudo() {
+        let error = || CommandOutput::new(String::new(), "permission denied".to_string());
+        let partly_sudo = Command::new("sudo make && make install".to_string(), error());
+        assert!(is_match(&partly_sudo));
+        let all_sudo = Command::new("sudo make && sudo make install".to_string(), error());
+        assert!(!is_match(&all_sudo));
+    }
+
+    #[test]
+    fn test_fix_chain_drops_inner_sudo() {
+        let command = Command::new(
+            "sudo apt update && apt install vim | tee log".to_string(),
+            CommandOutput::new(String::new(), "permission denied".to_string()),
+        );
+        assert_eq!(
+            fix(&command),
+            "sudo sh -c 'apt update && apt install vim | tee log'"
+        );
+    }
+
+    #[test]
+    fn test_fix_chain_drops_quoted_and_redirected_sudo() {
+        let command = Command::new(
+            ">log sudo make && 'sudo' make install".to_string(),
+            CommandOutput::new(String::new(), "permission denied".to_string()),
+        );
+        assert_eq!(fix(&command), "sudo sh -c '>log make && make install'");
+    }
+
+    #[test]
+    fn test_fix_quoted_operators_are_not_a_chain() {
+        let command = Command::new(
+            "echo 'a && b > c'".to_string(),
+            CommandOutput::new(String::new(), "permission denied".to_string()),
+        );
+        assert_eq!(fix(&command), "sudo echo 'a && b > c'");
+    }
+
+    #[test]
+    fn test_fix_only_failing_segment() {
+        let command = Command::new(
+            "make && make install > install.log | tee log".to_string(),
+            CommandOutput::new(String::new(), "permission denied".to_string()),
+        )
+        .with_pipe_status(vec![2, 0]);
+        assert_eq!(
+            fix(&command),
+            "make && sudo sh -c 'make install > install.log' | tee log"
+        );
+    }
+
+    #[test]
+    fn test_is_match_failing_segment_with_sudo() {
+        let command = Command::new(
+            "make && sudo make install".to_string(),
+            CommandOutput::new(String::new(), "permission denied".to_string()),
+        )
+        .with_pipe_status(vec![1]);
+        assert!(!is_match(&command));
+    }
 }
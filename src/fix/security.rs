@@ -0,0 +1,48 @@
+use crossterm::style::Stylize;
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+
+/// Rules are executed with the privileges of the current user,
+/// so they must be owned by that user and not be writable by anyone else.
+pub fn check_security(path: &Path) -> Result<(), String> {
+    let metadata = fs::metadata(path).map_err(|e| e.to_string())?;
+    let file_uid = metadata.uid();
+    let current_uid = unsafe { libc::geteuid() };
+
+    if current_uid != file_uid {
+        return Err(format!(
+            "{} Running with UID {}, but file '{}' is owned by UID {}. Aborting to prevent privilege escalation.",
+            "SECURITY ERROR:".red().bold(),
+            current_uid,
+            path.display(),
+            file_uid
+        ));
+    }
+
+    if metadata.permissions().mode() & 0o022 != 0 {
+        return Err(format!(
+            "{} Rule '{}' is writable by non-owners. Aborting to prevent privilege escalation.",
+            "SECURITY ERROR:".red().bold(),
+            path.display()
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_security_rejects_writable_by_others() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let rule = temp_dir.path().join("rule.py");
+        fs::write(&rule, "").unwrap();
+        fs::set_permissions(&rule, fs::Permissions::from_mode(0o666)).unwrap();
+        assert!(check_security(&rule).is_err());
+        fs::set_permissions(&rule, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(check_security(&rule).is_ok());
+    }
+}
@@ -0,0 +1,240 @@
+//! Per rule usage statistics, kept locally to see which rules are worth keeping.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Rule evaluations of the current run, rules are evaluated on several threads.
+static MEASUREMENTS: Mutex<Vec<Measurement>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Matched,
+    NotMatched,
+    Failed,
+}
+
+impl Outcome {
+    pub fn from_match(matched: bool) -> Self {
+        if matched {
+            Outcome::Matched
+        } else {
+            Outcome::NotMatched
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Measurement {
+    rule: String,
+    elapsed: Duration,
+    outcome: Outcome,
+}
+
+/// Records one evaluation of the rule for the statistics saved at the end of the run.
+pub fn measure(rule: String, elapsed: Duration, outcome: Outcome) {
+    if let Ok(mut measurements) = MEASUREMENTS.lock() {
+        measurements.push(Measurement {
+            rule,
+            elapsed,
+            outcome,
+        });
+    }
+}
+
+pub fn take_measurements() -> Vec<Measurement> {
+    MEASUREMENTS
+        .lock()
+        .map(|mut measurements| std::mem::take(&mut *measurements))
+        .unwrap_or_default()
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct RuleStats {
+    pub runs: u64,
+    pub matches: u64,
+    pub accepts: u64,
+    pub errors: u64,
+    total_micros: u64,
+}
+
+impl RuleStats {
+    pub fn average_time(&self) -> Duration {
+        Duration::from_micros(self.total_micros.checked_div(self.runs).unwrap_or_default())
+    }
+}
+
+/// The statistics file, a JSON object keyed by the rule file name.
+pub struct Stats {
+    path: PathBuf,
+    rules: BTreeMap<String, RuleStats>,
+}
+
+impl Stats {
+    pub fn open() -> io::Result<Self> {
+        Self::load(Self::path()?)
+    }
+
+    fn path() -> io::Result<PathBuf> {
+        Ok(dirs::data_dir()
+            .ok_or(ErrorKind::NotFound)?
+            .join("theshit/stats.json"))
+    }
+
+    fn load(path: PathBuf) -> io::Result<Self> {
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        // A damaged entry only loses its own rule, a damaged file starts over
+        let rules = serde_json::from_slice::<BTreeMap<String, serde_json::Value>>(&content)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(rule, stats)| Some((rule, serde_json::from_value(stats).ok()?)))
+            .collect();
+        Ok(Stats { path, rules })
+    }
+
+    pub fn rules(&self) -> &BTreeMap<String, RuleStats> {
+        &self.rules
+    }
+
+    pub fn add(&mut self, measurements: &[Measurement]) {
+        for measurement in measurements {
+            let stats = self.rules.entry(measurement.rule.clone()).or_default();
+            stats.runs += 1;
+            stats.total_micros += measurement.elapsed.as_micros() as u64;
+            match measurement.outcome {
+                Outcome::Matched => stats.matches += 1,
+                Outcome::NotMatched => {}
+                Outcome::Failed => stats.errors += 1,
+            }
+        }
+    }
+
+    pub fn accept(&mut self, rule: &str) {
+        self.rules.entry(rule.to_string()).or_default().accepts += 1;
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Written aside and renamed, so a crash doesn't lose the statistics.
+        // Rule names tell what the user runs, only the user can read them
+        let temporary_path = self.path.with_extension("tmp");
+        let _ = fs::remove_file(&temporary_path);
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&temporary_path)?;
+        serde_json::to_writer_pretty(file, &self.rules)?;
+        fs::rename(&temporary_path, &self.path)
+    }
+
+    /// Deletes the statistics without reading them, so a file that can't be read goes too.
+    pub fn clear() -> io::Result<()> {
+        remove(&Self::path()?)
+    }
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn measurement(rule: &str, micros: u64, outcome: Outcome) -> Measurement {
+        Measurement {
+            rule: rule.to_string(),
+            elapsed: Duration::from_micros(micros),
+            outcome,
+        }
+    }
+
+    #[test]
+    fn test_add_and_accept() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("theshit/stats.json");
+        let mut stats = Stats::load(path.clone()).unwrap();
+        stats.add(&[
+            measurement("sudo.native", 100, Outcome::Matched),
+            measurement("sudo.native", 300, Outcome::NotMatched),
+            measurement("typo.py", 5000, Outcome::Failed),
+        ]);
+        stats.accept("sudo.native");
+        stats.save().unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let stats = Stats::load(path).unwrap();
+        let sudo = &stats.rules()["sudo.native"];
+        assert_eq!(
+            (sudo.runs, sudo.matches, sudo.accepts, sudo.errors),
+            (2, 1, 1, 0)
+        );
+        assert_eq!(sudo.average_time(), Duration::from_micros(200));
+        assert_eq!(stats.rules()["typo.py"].errors, 1);
+    }
+
+    #[test]
+    fn test_average_time_without_runs() {
+        assert_eq!(RuleStats::default().average_time(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_measure() {
+        take_measurements();
+        measure(
+            "to_cd.native".to_string(),
+            Duration::from_micros(10),
+            Outcome::from_match(true),
+        );
+        let measurements = take_measurements();
+        assert!(
+            measurements
+                .iter()
+                .any(|measurement| measurement.rule == "to_cd.native"
+                    && measurement.outcome == Outcome::Matched)
+        );
+    }
+
+    #[test]
+    fn test_load_skips_damaged_entries() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("stats.json");
+        let mut stats = Stats::load(path.clone()).unwrap();
+        stats.accept("sudo.native");
+        stats.save().unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        let damaged = content.replacen('{', r#"{"typo.py": {"runs": -1},"#, 1);
+        fs::write(&path, damaged).unwrap();
+        let stats = Stats::load(path.clone()).unwrap();
+        assert_eq!(stats.rules().keys().collect::<Vec<_>>(), ["sudo.native"]);
+
+        fs::write(&path, "{\"sudo.native\": {").unwrap();
+        assert!(Stats::load(path).unwrap().rules().is_empty());
+    }
+
+    #[test]
+    fn test_remove_damaged_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("stats.json");
+        fs::write(&path, [0xff, 0xfe]).unwrap();
+        remove(&path).unwrap();
+        assert!(!path.exists());
+        assert!(remove(&path).is_ok());
+    }
+}
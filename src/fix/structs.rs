@@ -1,5 +1,7 @@
+use super::syntax::{CommandLine, SimpleCommand};
 use crate::misc;
 use crossterm::terminal;
+use std::path::Path;
 use std::process::Output;
 
 pub struct RawModeGuard;
@@ -17,14 +19,47 @@ impl Drop for RawModeGuard {
     }
 }
 
+/// How the failed command is re-run to capture its output.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RunMode {
+    /// Through the user's shell, so builtins, pipes and expansions behave as they did there
+    #[default]
+    Shell,
+    /// Spawned directly, faster but only right for simple commands
+    Spawn,
+}
+
+/// Settings for re-running the failed command.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RerunOptions {
+    pub run_mode: RunMode,
+    /// Connect stdout and stderr to pseudo-terminals, as tools print differently to pipes
+    pub pty: bool,
+    /// Keep the user's locale instead of forcing `LANG=C`
+    pub native_locale: bool,
+    /// Run the command a second time in the user's locale for the rules that ask for it
+    pub native_locale_rerun: bool,
+    /// The command hasn't run, so it isn't run at all and only the rules that need no output apply
+    pub text_only: bool,
+}
+
+/// Priority of fixes from rules that don't set one; lower values are suggested first.
+pub const DEFAULT_PRIORITY: i32 = 1000;
+
+#[derive(Default)]
 pub struct CommandOutput {
     stdout: String,
     stderr: String,
+    exit_code: Option<i32>,
 }
 
 impl CommandOutput {
     pub fn new(stdout: String, stderr: String) -> Self {
-        CommandOutput { stdout, stderr }
+        CommandOutput {
+            stdout,
+            stderr,
+            exit_code: None,
+        }
     }
 
     pub fn stdout(&self) -> &str {
@@ -34,32 +69,51 @@ impl CommandOutput {
     pub fn stderr(&self) -> &str {
         &self.stderr
     }
+
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
 }
 
 impl From<Output> for CommandOutput {
     fn from(output: Output) -> Self {
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-        CommandOutput { stdout, stderr }
+        // Rules match plain text, whatever colours the command printed
+        let stdout = misc::strip_ansi_escapes(&String::from_utf8_lossy(&output.stdout));
+        let stderr = misc::strip_ansi_escapes(&String::from_utf8_lossy(&output.stderr));
+        CommandOutput {
+            stdout,
+            stderr,
+            exit_code: output.status.code(),
+        }
     }
 }
 
 pub struct Command {
     command: String,
     parts: Vec<String>,
+    script: Option<CommandLine>,
+    pipe_status: Vec<i32>,
     output: CommandOutput,
 }
 
 impl Command {
     pub fn new(command: String, output: CommandOutput) -> Self {
         let parts = misc::split_command(&command);
+        let script = CommandLine::parse(&command).ok();
         Command {
             command,
             parts,
+            script,
+            pipe_status: Vec::new(),
             output,
         }
     }
 
+    pub fn with_pipe_status(mut self, pipe_status: Vec<i32>) -> Self {
+        self.pipe_status = pipe_status;
+        self
+    }
+
     pub fn command(&self) -> &str {
         &self.command
     }
@@ -68,9 +122,90 @@ impl Command {
         &self.parts
     }
 
+    /// The command parsed into pipelines and simple commands, `None` if it isn't valid shell syntax.
+    pub fn script(&self) -> Option<&CommandLine> {
+        self.script.as_ref()
+    }
+
     pub fn output(&self) -> &CommandOutput {
         &self.output
     }
+
+    /// Exit statuses of the last pipeline the shell ran, as reported by the shell.
+    pub fn pipe_status(&self) -> &[i32] {
+        &self.pipe_status
+    }
+
+    /// Index of the simple command that failed, counted over the whole command line.
+    ///
+    /// The shell only reports the statuses of the last pipeline it ran. That is the last
+    /// pipeline of the line unless a `&&` cut it short, so when several pipelines have
+    /// as many commands, the one whose program the output mentions is taken. Without the
+    /// statuses, as from Nushell, it is unknown even for a single command.
+    pub fn failing_command(&self) -> Option<usize> {
+        let script = self.script.as_ref()?;
+        let position = self.pipe_status.iter().position(|&status| status != 0)?;
+        let mut first_index = 0;
+        let mut candidates = Vec::new();
+        for pipeline in script.pipelines() {
+            if pipeline.commands().len() == self.pipe_status.len() {
+                candidates.push((first_index, &pipeline.commands()[position]));
+            }
+            first_index += pipeline.commands().len();
+        }
+        let output = format!("{}\n{}", self.output.stdout, self.output.stderr);
+        let mentioned = |command: &&(usize, &SimpleCommand)| {
+            command.1.program().is_some_and(|program| {
+                let name = program.rsplit('/').next().unwrap_or(program);
+                output.contains(&format!("{name}:"))
+            })
+        };
+        let (first_index, _) = candidates.iter().rfind(mentioned).or(candidates.last())?;
+        Some(first_index + position)
+    }
+}
+
+pub struct FixedCommand {
+    command: String,
+    priority: i32,
+    rule: String,
+}
+
+impl FixedCommand {
+    pub fn new(command: String, priority: i32, rule: String) -> Self {
+        FixedCommand {
+            command,
+            priority,
+            rule,
+        }
+    }
+
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// File name of the rule that suggested the fix, like `sudo.native`.
+    pub fn rule(&self) -> &str {
+        &self.rule
+    }
+}
+
+/// Name a rule file is known by in the history and the statistics.
+pub fn rule_name(rule_path: &Path) -> String {
+    rule_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+impl From<FixedCommand> for String {
+    fn from(fixed_command: FixedCommand) -> Self {
+        fixed_command.command
+    }
 }
 
 #[cfg(test)]
@@ -98,6 +233,7 @@ mod tests {
         let output = CommandOutput::new("test stdout".to_string(), "test stderr".to_string());
         assert_eq!(output.stdout(), "test stdout");
         assert_eq!(output.stderr(), "test stderr");
+        assert_eq!(output.exit_code(), None);
     }
 
     #[test]
@@ -110,6 +246,7 @@ mod tests {
         let output = CommandOutput::from(process_output);
         assert_eq!(output.stdout(), "test stdout");
         assert_eq!(output.stderr(), "test stderr");
+        assert_eq!(output.exit_code(), Some(0));
     }
 
     #[test]
@@ -137,4 +274,47 @@ mod tests {
         assert_eq!(command.command(), "");
         assert!(command.parts().is_empty());
     }
+
+    #[test]
+    fn failing_command_of_simple_command() {
+        let command = Command::new("ls /root".to_string(), CommandOutput::default())
+            .with_pipe_status(vec![2]);
+        assert_eq!(command.failing_command(), Some(0));
+        let command = Command::new("ls /root".to_string(), CommandOutput::default());
+        assert_eq!(command.failing_command(), None);
+    }
+
+    #[test]
+    fn failing_command_in_pipeline() {
+        let command = Command::new("cat missing | grep x".to_string(), CommandOutput::default())
+            .with_pipe_status(vec![1, 1]);
+        assert_eq!(command.failing_command(), Some(0));
+        let command = Command::new("cat file | grepp x".to_string(), CommandOutput::default())
+            .with_pipe_status(vec![0, 127]);
+        assert_eq!(command.failing_command(), Some(1));
+    }
+
+    #[test]
+    fn failing_command_in_chain() {
+        let output = CommandOutput::new(
+            String::new(),
+            "mkdir: cannot create directory 'a/b': No such file or directory".to_string(),
+        );
+        let command =
+            Command::new("mkdir a/b && cd a/b".to_string(), output).with_pipe_status(vec![1]);
+        assert_eq!(command.failing_command(), Some(0));
+        // Without a hint in the output the last possible pipeline is taken
+        let command = Command::new("ls; false".to_string(), CommandOutput::default())
+            .with_pipe_status(vec![1]);
+        assert_eq!(command.failing_command(), Some(1));
+        let command = Command::new("ls | wc -l; false".to_string(), CommandOutput::default())
+            .with_pipe_status(vec![0, 1]);
+        assert_eq!(command.failing_command(), Some(1));
+    }
+
+    #[test]
+    fn failing_command_unknown() {
+        let command = Command::new("ls && false".to_string(), CommandOutput::default());
+        assert_eq!(command.failing_command(), None);
+    }
 }
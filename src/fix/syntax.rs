@@ -0,0 +1,572 @@
+//! A parser for the POSIX shell syntax used in command lines: lists of pipelines joined by
+//! `&&`, `||`, `;` or `&`, made of simple commands with words and redirections.
+//! Compound commands like `( ... )`, `$( ... )` and backticks are kept as opaque words.
+use serde::Serialize;
+use std::ops::Range;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Separator {
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+    /// `;` or a newline
+    Sequence,
+    /// `&`
+    Background,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SimpleCommand {
+    words: Vec<String>,
+    #[serde(skip)]
+    word_spans: Vec<Range<usize>>,
+    redirections: Vec<String>,
+    span: Range<usize>,
+}
+
+impl SimpleCommand {
+    /// Words with quotes removed and escapes resolved, substitutions are kept as written.
+    pub fn words(&self) -> &[String] {
+        &self.words
+    }
+
+    /// Byte ranges of the words in the parsed command line, quotes included.
+    pub fn word_spans(&self) -> &[Range<usize>] {
+        &self.word_spans
+    }
+
+    /// Redirections as written, like `> out.txt` or `2>&1`.
+    pub fn redirections(&self) -> &[String] {
+        &self.redirections
+    }
+
+    /// Byte range of the command in the parsed command line.
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+
+    pub fn program(&self) -> Option<&str> {
+        self.words.first().map(String::as_str)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    commands: Vec<SimpleCommand>,
+    /// Separator after the pipeline, `None` for the last one
+    separator: Option<Separator>,
+}
+
+impl Pipeline {
+    pub fn commands(&self) -> &[SimpleCommand] {
+        &self.commands
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandLine {
+    source: String,
+    pipelines: Vec<Pipeline>,
+}
+
+impl CommandLine {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        Parser::new(source).parse()
+    }
+
+    pub fn pipelines(&self) -> &[Pipeline] {
+        &self.pipelines
+    }
+
+    /// All simple commands in the order they appear.
+    pub fn simple_commands(&self) -> impl Iterator<Item = &SimpleCommand> {
+        self.pipelines
+            .iter()
+            .flat_map(|pipeline| pipeline.commands.iter())
+    }
+
+    /// Whether the command line is more than a single simple command.
+    pub fn is_compound(&self) -> bool {
+        self.simple_commands().nth(1).is_some()
+    }
+
+    /// Rebuilds the command line, replacing the source of every simple command
+    /// for which `rewrite` returns a replacement; the rest is kept as written.
+    pub fn rewrite(
+        &self,
+        mut rewrite: impl FnMut(usize, &SimpleCommand, &str) -> Option<String>,
+    ) -> String {
+        let mut result = String::with_capacity(self.source.len());
+        let mut position = 0;
+        for (index, command) in self.simple_commands().enumerate() {
+            if let Some(replacement) = rewrite(index, command, &self.source[command.span()]) {
+                result.push_str(&self.source[position..command.span.start]);
+                result.push_str(&replacement);
+                position = command.span.end;
+            }
+        }
+        result.push_str(&self.source[position..]);
+        result
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    chars: Vec<(usize, char)>,
+    position: usize,
+    pipelines: Vec<Pipeline>,
+    commands: Vec<SimpleCommand>,
+    words: Vec<String>,
+    word_spans: Vec<Range<usize>>,
+    redirections: Vec<String>,
+    span: Option<Range<usize>>,
+    heredocs: Vec<String>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Self {
+        Parser {
+            source,
+            chars: source.char_indices().collect(),
+            position: 0,
+            pipelines: Vec::new(),
+            commands: Vec::new(),
+            words: Vec::new(),
+            word_spans: Vec::new(),
+            redirections: Vec::new(),
+            span: None,
+            heredocs: Vec::new(),
+        }
+    }
+
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.position + offset).map(|&(_, c)| c)
+    }
+
+    fn offset(&self) -> usize {
+        self.chars
+            .get(self.position)
+            .map_or(self.source.len(), |&(offset, _)| offset)
+    }
+
+    fn parse(mut self) -> Result<CommandLine, String> {
+        while let Some(c) = self.peek(0) {
+            match c {
+                ' ' | '\t' => self.position += 1,
+                '\\' if self.peek(1) == Some('\n') => self.position += 2,
+                '#' => {
+                    while self.peek(0).is_some_and(|c| c != '\n') {
+                        self.position += 1;
+                    }
+                }
+                '\n' => {
+                    self.position += 1;
+                    self.skip_heredocs()?;
+                    self.end_pipeline(Separator::Sequence);
+                }
+                ';' => {
+                    self.position += if self.peek(1) == Some(';') { 2 } else { 1 };
+                    self.end_pipeline(Separator::Sequence);
+                }
+                '&' if self.peek(1) == Some('&') => {
+                    self.position += 2;
+                    self.end_pipeline(Separator::And);
+                }
+                '&' if self.peek(1) == Some('>') => self.parse_redirection()?,
+                '&' => {
+                    self.position += 1;
+                    self.end_pipeline(Separator::Background);
+                }
+                '|' if self.peek(1) == Some('|') => {
+                    self.position += 2;
+                    self.end_pipeline(Separator::Or);
+                }
+                '|' => {
+                    self.position += if self.peek(1) == Some('&') { 2 } else { 1 };
+                    self.end_command();
+                }
+                '<' | '>' => self.parse_redirection()?,
+                c if c.is_ascii_digit() && self.is_fd_redirection() => self.parse_redirection()?,
+                _ => {
+                    let start = self.offset();
+                    let word = self.parse_word()?;
+                    self.extend_span(start);
+                    self.words.push(word);
+                    self.word_spans.push(start..self.offset());
+                }
+            }
+        }
+        if !self.heredocs.is_empty() {
+            return Err("unterminated here-document".to_string());
+        }
+        self.end_command();
+        if let Some(pipeline) = self.pipelines.last_mut()
+            && self.commands.is_empty()
+            && pipeline.separator == Some(Separator::Sequence)
+        {
+            // A trailing `;` or newline doesn't separate anything
+            pipeline.separator = None;
+        }
+        if !self.commands.is_empty() {
+            self.pipelines.push(Pipeline {
+                commands: self.commands,
+                separator: None,
+            });
+        }
+        Ok(CommandLine {
+            source: self.source.to_string(),
+            pipelines: self.pipelines,
+        })
+    }
+
+    fn extend_span(&mut self, start: usize) {
+        let end = self.offset();
+        self.span = Some(match self.span.take() {
+            Some(span) => span.start..end,
+            None => start..end,
+        });
+    }
+
+    fn end_command(&mut self) {
+        if let Some(span) = self.span.take() {
+            self.commands.push(SimpleCommand {
+                words: std::mem::take(&mut self.words),
+                word_spans: std::mem::take(&mut self.word_spans),
+                redirections: std::mem::take(&mut self.redirections),
+                span,
+            });
+        }
+    }
+
+    fn end_pipeline(&mut self, separator: Separator) {
+        self.end_command();
+        if !self.commands.is_empty() {
+            self.pipelines.push(Pipeline {
+                commands: std::mem::take(&mut self.commands),
+                separator: Some(separator),
+            });
+        }
+    }
+
+    /// `2>file` and `10>&1` are redirections, `2 >file` and `a2>file` are not.
+    fn is_fd_redirection(&self) -> bool {
+        let mut offset = 0;
+        while self.peek(offset).is_some_and(|c| c.is_ascii_digit()) {
+            offset += 1;
+        }
+        matches!(self.peek(offset), Some('<' | '>'))
+    }
+
+    fn parse_redirection(&mut self) -> Result<(), String> {
+        let start = self.offset();
+        while self.peek(0).is_some_and(|c| c.is_ascii_digit()) {
+            self.position += 1;
+        }
+        let operator_start = self.position;
+        for operator in [
+            "&>>", "<<<", "<<-", "&>", ">>", ">&", ">|", "<<", "<&", "<>", ">", "<",
+        ] {
+            if operator
+                .chars()
+                .enumerate()
+                .all(|(i, c)| self.peek(i) == Some(c))
+            {
+                self.position += operator.len();
+                break;
+            }
+        }
+        let is_heredoc = matches!(
+            &self.source[self.chars[operator_start].0..self.offset()],
+            "<<" | "<<-"
+        );
+        while matches!(self.peek(0), Some(' ' | '\t')) {
+            self.position += 1;
+        }
+        if self.peek(0).is_none_or(|c| "\n;&|<>".contains(c)) {
+            return Err("redirection without a target".to_string());
+        }
+        let target = self.parse_word()?;
+        if is_heredoc {
+            self.heredocs.push(target);
+        }
+        self.redirections
+            .push(self.source[start..self.offset()].to_string());
+        self.extend_span(start);
+        Ok(())
+    }
+
+    /// Skips the bodies of the here-documents started on the line that just ended.
+    fn skip_heredocs(&mut self) -> Result<(), String> {
+        for delimiter in std::mem::take(&mut self.heredocs) {
+            loop {
+                if self.peek(0).is_none() {
+                    return Err("unterminated here-document".to_string());
+                }
+                let line_start = self.offset();
+                while self.peek(0).is_some_and(|c| c != '\n') {
+                    self.position += 1;
+                }
+                let line = &self.source[line_start..self.offset()];
+                self.position += 1;
+                if line.trim_start_matches('\t') == delimiter {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_word(&mut self) -> Result<String, String> {
+        let mut word = String::new();
+        while let Some(c) = self.peek(0) {
+            match c {
+                ' ' | '\t' | '\n' | ';' | '&' | '|' | '<' | '>' => break,
+                '\\' => {
+                    match self.peek(1) {
+                        Some('\n') => {}
+                        Some(escaped) => word.push(escaped),
+                        None => word.push('\\'),
+                    }
+                    self.position += 2;
+                }
+                '\'' => {
+                    self.position += 1;
+                    loop {
+                        match self.peek(0) {
+                            Some('\'') => break,
+                            Some(c) => word.push(c),
+                            None => return Err("unterminated single quote".to_string()),
+                        }
+                        self.position += 1;
+                    }
+                    self.position += 1;
+                }
+                '"' => {
+                    self.position += 1;
+                    loop {
+                        match self.peek(0) {
+                            Some('"') => break,
+                            Some('\\') if self.peek(1).is_some_and(|c| "$`\"\\\n".contains(c)) => {
+                                if self.peek(1) != Some('\n') {
+                                    word.push(self.peek(1).unwrap_or_default());
+                                }
+                                self.position += 2;
+                            }
+                            Some('$') if matches!(self.peek(1), Some('(' | '{')) => {
+                                word.push_str(&self.parse_substitution()?);
+                            }
+                            Some('`') => word.push_str(&self.parse_backticks()?),
+                            Some(c) => {
+                                word.push(c);
+                                self.position += 1;
+                            }
+                            None => return Err("unterminated double quote".to_string()),
+                        }
+                    }
+                    self.position += 1;
+                }
+                '$' if matches!(self.peek(1), Some('(' | '{')) => {
+                    word.push_str(&self.parse_substitution()?);
+                }
+                '`' => word.push_str(&self.parse_backticks()?),
+                '(' => word.push_str(&self.parse_group('(', ')')?),
+                _ => {
+                    word.push(c);
+                    self.position += 1;
+                }
+            }
+        }
+        Ok(word)
+    }
+
+    /// `$( ... )`, `$(( ... ))` and `${ ... }`, returned as written.
+    fn parse_substitution(&mut self) -> Result<String, String> {
+        let start = self.offset();
+        self.position += 1;
+        match self.peek(0) {
+            Some('{') => self.parse_group('{', '}')?,
+            _ => self.parse_group('(', ')')?,
+        };
+        Ok(self.source[start..self.offset()].to_string())
+    }
+
+    /// A balanced group starting at `open`, returned as written.
+    fn parse_group(&mut self, open: char, close: char) -> Result<String, String> {
+        let start = self.offset();
+        let mut depth = 0;
+        while let Some(c) = self.peek(0) {
+            match c {
+                '\\' => self.position += 1,
+                '\'' if open == '(' => {
+                    self.position += 1;
+                    while self.peek(0).is_some_and(|c| c != '\'') {
+                        self.position += 1;
+                    }
+                }
+                '"' => {
+                    self.position += 1;
+                    while let Some(c) = self.peek(0) {
+                        match c {
+                            '"' => break,
+                            '\\' => self.position += 2,
+                            _ => self.position += 1,
+                        }
+                    }
+                }
+                c if c == open => depth += 1,
+                c if c == close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.position += 1;
+                        return Ok(self.source[start..self.offset()].to_string());
+                    }
+                }
+                _ => {}
+            }
+            self.position += 1;
+        }
+        Err(format!("unterminated `{open}`"))
+    }
+
+    fn parse_backticks(&mut self) -> Result<String, String> {
+        let start = self.offset();
+        self.position += 1;
+        loop {
+            match self.peek(0) {
+                Some('`') => break,
+                Some('\\') => self.position += 2,
+                Some(_) => self.position += 1,
+                None => return Err("unterminated backtick".to_string()),
+            }
+        }
+        self.position += 1;
+        Ok(self.source[start..self.offset()].to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn programs(command_line: &CommandLine) -> Vec<&str> {
+        command_line
+            .simple_commands()
+            .filter_map(SimpleCommand::program)
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_simple_command() {
+        let command_line = CommandLine::parse("git commit -m 'initial commit'").unwrap();
+        assert!(!command_line.is_compound());
+        let command = command_line.simple_commands().next().unwrap();
+        assert_eq!(command.words(), ["git", "commit", "-m", "initial commit"]);
+        assert_eq!(command.span(), 0..30);
+        assert_eq!(command.word_spans(), [0..3, 4..10, 11..13, 14..30]);
+    }
+
+    #[test]
+    fn test_parse_chains() {
+        let command_line = CommandLine::parse("make && make install || echo failed; ls &").unwrap();
+        let separators: Vec<_> = command_line
+            .pipelines()
+            .iter()
+            .map(|pipeline| pipeline.separator)
+            .collect();
+        assert_eq!(
+            separators,
+            [
+                Some(Separator::And),
+                Some(Separator::Or),
+                Some(Separator::Sequence),
+                Some(Separator::Background),
+            ]
+        );
+        assert_eq!(programs(&command_line), ["make", "make", "echo", "ls"]);
+    }
+
+    #[test]
+    fn test_parse_pipeline() {
+        let command_line = CommandLine::parse("cat file | grep 'a|b' |& less").unwrap();
+        assert_eq!(command_line.pipelines().len(), 1);
+        assert_eq!(programs(&command_line), ["cat", "grep", "less"]);
+        assert_eq!(
+            command_line.simple_commands().nth(1).unwrap().words()[1],
+            "a|b"
+        );
+    }
+
+    #[test]
+    fn test_parse_redirections() {
+        let command_line = CommandLine::parse("make 2>&1 >build.log && cat < in.txt").unwrap();
+        let commands: Vec<_> = command_line.simple_commands().collect();
+        assert_eq!(commands[0].words(), ["make"]);
+        assert_eq!(commands[0].redirections(), ["2>&1", ">build.log"]);
+        assert_eq!(commands[1].redirections(), ["< in.txt"]);
+        // A digit inside a word isn't a file descriptor
+        let command_line = CommandLine::parse("echo a2>file").unwrap();
+        let command = command_line.simple_commands().next().unwrap();
+        assert_eq!(command.words(), ["echo", "a2"]);
+    }
+
+    #[test]
+    fn test_parse_quotes_and_substitutions() {
+        let command_line =
+            CommandLine::parse(r#"echo "$(date +%s) && ${HOME}" `pwd` \; "a\"b" (cd /tmp; ls)"#)
+                .unwrap();
+        assert!(!command_line.is_compound());
+        let command = command_line.simple_commands().next().unwrap();
+        assert_eq!(
+            command.words(),
+            [
+                "echo",
+                "$(date +%s) && ${HOME}",
+                "`pwd`",
+                ";",
+                "a\"b",
+                "(cd /tmp; ls)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_multiline() {
+        let command_line =
+            CommandLine::parse("cat <<EOF | wc -l\nfoo && bar\nEOF\nls \\\n  -la\n").unwrap();
+        assert_eq!(programs(&command_line), ["cat", "wc", "ls"]);
+        assert_eq!(command_line.pipelines()[1].separator, None);
+        assert_eq!(
+            command_line.simple_commands().last().unwrap().words(),
+            ["ls", "-la"]
+        );
+    }
+
+    #[test]
+    fn test_parse_comment() {
+        let command_line = CommandLine::parse("ls # list && files").unwrap();
+        assert_eq!(programs(&command_line), ["ls"]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(CommandLine::parse("echo 'unterminated").is_err());
+        assert!(CommandLine::parse("echo \"unterminated").is_err());
+        assert!(CommandLine::parse("echo $(unterminated").is_err());
+        assert!(CommandLine::parse("cat <<EOF\nno end").is_err());
+        assert!(CommandLine::parse("echo >").is_err());
+    }
+
+    #[test]
+    fn test_rewrite_keeps_the_rest() {
+        let command_line = CommandLine::parse("mkdir a/b  &&  cd a/b | cat").unwrap();
+        let rewritten =
+            command_line.rewrite(|index, _, _| (index == 2).then(|| "less".to_string()));
+        assert_eq!(rewritten, "mkdir a/b  &&  cd a/b | less");
+        let rewritten = command_line.rewrite(|_, command, source| {
+            (command.program() == Some("cd")).then(|| source.replace("cd", "pushd"))
+        });
+        assert_eq!(rewritten, "mkdir a/b  &&  pushd a/b | cat");
+    }
+}
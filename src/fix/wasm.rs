@@ -0,0 +1,210 @@
+//! WebAssembly rules.
+//!
+//! A rule module exports its `memory` and two functions:
+//! - `alloc(len: i32) -> i32` returns a buffer of `len` bytes for the host to write to;
+//! - `fix(ptr: i32, len: i32) -> i64` receives the rule input JSON in that buffer and returns
+//!   the location of the reply JSON packed as `ptr << 32 | len`, or `0` if nothing matched.
+//!
+//! Modules get no imports, so a rule has no filesystem or network access,
+//! and its memory and execution time are limited.
+use super::protocol::{RuleInput, RuleOutput};
+use super::security::check_security;
+use super::stats::{self, Outcome};
+use super::structs::{Command, FixedCommand, rule_name};
+use crossterm::style::Stylize;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use std::{fs, thread};
+use wasmi::{Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+static FUEL: u64 = 500_000_000;
+static MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+pub fn process_wasm_rules(
+    command: &Command,
+    shell: &str,
+    rule_paths: Vec<PathBuf>,
+) -> Vec<FixedCommand> {
+    let input = RuleInput::new(command, shell).to_json();
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config);
+
+    thread::scope(|scope| {
+        rule_paths
+            .iter()
+            .map(|rule_path| {
+                scope.spawn(|| {
+                    let started = Instant::now();
+                    let result = run_rule(&engine, rule_path, &input, FUEL);
+                    (result, started.elapsed())
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .zip(&rule_paths)
+            .flat_map(|(handle, rule_path)| {
+                let (result, elapsed) = handle.join().expect("WebAssembly rule thread panicked");
+                let rule = rule_name(rule_path);
+                match result {
+                    Ok(output) => {
+                        let fixed_commands = output
+                            .map(|output| output.into_fixed_commands(&rule))
+                            .unwrap_or_default();
+                        stats::measure(
+                            rule,
+                            elapsed,
+                            Outcome::from_match(!fixed_commands.is_empty()),
+                        );
+                        fixed_commands
+                    }
+                    Err(e) => {
+                        stats::measure(rule, elapsed, Outcome::Failed);
+                        eprintln!(
+                            "{}{}{}{}",
+                            "WebAssembly rule '".yellow(),
+                            rule_path.display(),
+                            "' failed: ".yellow(),
+                            e
+                        );
+                        vec![]
+                    }
+                }
+            })
+            .collect()
+    })
+}
+
+fn run_rule(
+    engine: &Engine,
+    rule_path: &Path,
+    input: &[u8],
+    fuel: u64,
+) -> Result<Option<RuleOutput>, String> {
+    check_security(rule_path)?;
+    let wasm = fs::read(rule_path).map_err(|e| e.to_string())?;
+    let module = Module::new(engine, &wasm).map_err(|e| e.to_string())?;
+
+    let mut store: Store<StoreLimits> = Store::new(
+        engine,
+        StoreLimitsBuilder::new()
+            .memory_size(MEMORY_LIMIT)
+            .instances(1)
+            .build(),
+    );
+    store.limiter(|limits| limits);
+    store.set_fuel(fuel).map_err(|e| e.to_string())?;
+    let instance = Linker::<StoreLimits>::new(engine)
+        .instantiate(&mut store, &module)
+        .and_then(|instance| instance.start(&mut store))
+        .map_err(|e| e.to_string())?;
+
+    let memory = instance
+        .get_memory(&store, "memory")
+        .ok_or("missing exported memory")?;
+    let alloc = instance
+        .get_typed_func::<i32, i32>(&store, "alloc")
+        .map_err(|e| format!("invalid `alloc` export: {e}"))?;
+    let fix = instance
+        .get_typed_func::<(i32, i32), i64>(&store, "fix")
+        .map_err(|e| format!("invalid `fix` export: {e}"))?;
+
+    let input_len = i32::try_from(input.len()).map_err(|_| "rule input is too large")?;
+    let input_ptr = alloc
+        .call(&mut store, input_len)
+        .map_err(|e| e.to_string())?;
+    memory
+        .write(&mut store, input_ptr as u32 as usize, input)
+        .map_err(|e| e.to_string())?;
+    let reply = fix
+        .call(&mut store, (input_ptr, input_len))
+        .map_err(|e| e.to_string())?;
+    if reply == 0 {
+        return Ok(None);
+    }
+
+    let (reply_ptr, reply_len) = ((reply as u64 >> 32) as usize, reply as u32 as usize);
+    let mut output = vec![0; reply_len];
+    memory
+        .read(&store, reply_ptr, &mut output)
+        .map_err(|e| e.to_string())?;
+    RuleOutput::from_json(&output).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fix::structs::CommandOutput;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Always replies with `reply`, or with nothing when it's `None`.
+    fn rule_wat(reply: Option<&str>) -> String {
+        let (data, fix_result) = match reply {
+            Some(reply) => (
+                format!(r#"(data (i32.const 0) "{}")"#, reply.replace('"', "\\\"")),
+                reply.len(),
+            ),
+            None => (String::new(), 0),
+        };
+        format!(
+            r#"(module
+                (memory (export "memory") 1)
+                {data}
+                (func (export "alloc") (param i32) (result i32) (i32.const 4096))
+                (func (export "fix") (param i32 i32) (result i64) (i64.const {fix_result})))"#
+        )
+    }
+
+    fn write_rule(dir: &Path, name: &str, wat: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, wat::parse_str(wat).unwrap()).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_process_wasm_rules() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let matching_rule = write_rule(
+            temp_dir.path(),
+            "matching.wasm",
+            &rule_wat(Some(
+                r#"{"matched": true, "fixes": ["git push"], "priority": 10}"#,
+            )),
+        );
+        let other_rule = write_rule(temp_dir.path(), "other.wasm", &rule_wat(None));
+        let command = Command::new("git psuh".to_string(), CommandOutput::default());
+        let fixed_commands = process_wasm_rules(&command, "bash", vec![matching_rule, other_rule]);
+        assert_eq!(fixed_commands.len(), 1);
+        assert_eq!(fixed_commands[0].command(), "git push");
+        assert_eq!(fixed_commands[0].priority(), 10);
+        assert_eq!(fixed_commands[0].rule(), "matching.wasm");
+    }
+
+    #[test]
+    fn test_run_rule_rejects_imports() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let rule = write_rule(
+            temp_dir.path(),
+            "imports.wasm",
+            r#"(module (import "wasi_snapshot_preview1" "fd_write" (func (param i32 i32 i32 i32) (result i32))))"#,
+        );
+        let engine = Engine::new(Config::default().consume_fuel(true));
+        assert!(run_rule(&engine, &rule, b"{}", FUEL).is_err());
+    }
+
+    #[test]
+    fn test_run_rule_runs_out_of_fuel() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let rule = write_rule(
+            temp_dir.path(),
+            "endless.wasm",
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                (func (export "fix") (param i32 i32) (result i64) (loop $endless (br $endless)) (i64.const 0)))"#,
+        );
+        let engine = Engine::new(Config::default().consume_fuel(true));
+        assert!(run_rule(&engine, &rule, b"{}", 10_000).is_err());
+    }
+}
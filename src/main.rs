@@ -2,6 +2,7 @@
 //!
 //! See [README](https://github.com/AsfhtgkDavid/theshit) for more details.
 mod cli;
+mod doctor;
 mod fix;
 mod misc;
 mod shells;
@@ -11,7 +12,9 @@ use cli::{Cli, Command};
 use crossterm::style::Stylize;
 use std::env;
 use std::io::ErrorKind;
+use std::path::PathBuf;
 use std::str::FromStr;
+use strum::IntoEnumIterator;
 
 fn main() {
     #[cfg(not(feature = "standard_panic"))]
@@ -19,33 +22,175 @@ fn main() {
 
     let args = Cli::parse();
 
-    let shell = args
-        .shell
-        .and_then(|shell| shells::Shell::from_str(&shell).ok())
-        .or(shells::get_current_shell())
-        .expect("Could not determine the current shell.");
+    // Only the commands that need the shell detect it, the doctor checks the detection itself
+    let shell = || {
+        args.shell
+            .as_deref()
+            .and_then(|shell| shells::Shell::from_str(shell).ok())
+            .or_else(shells::get_current_shell)
+            .expect("Could not determine the current shell.")
+    };
 
     match args.command {
-        Command::Alias { name } => {
+        Command::Alias {
+            name,
+            replace_history,
+            key_binding,
+        } => {
+            let shell = shell();
             let program_path =
                 env::current_exe().expect("Could not determine the current executable path.");
-            let alias = shell.get_shell_function(&name, program_path.as_path());
+            let options = shells::AliasOptions {
+                replace_history,
+                key_binding,
+            };
+            reject_unsupported_options(&shell, options);
+            let alias = shell.get_shell_function(&name, program_path.as_path(), options);
             println!("{alias}");
         }
-        Command::Fix => {
+        Command::Fix {
+            raw,
+            pty,
+            text_only,
+            native_locale,
+        } => {
+            let shell = shell();
             let command =
                 env::var("SH_PREV_CMD").expect("SH_PREV_CMD environment variable is not set.");
-            let expand_command = misc::expand_aliases(&command, shell.get_aliases());
-            let fixed_command = fix::fix_command(command, expand_command);
+            let expand_command = shell.expand_command(&command);
+            let options = fix::RerunOptions {
+                run_mode: if raw {
+                    fix::RunMode::Spawn
+                } else {
+                    fix::RunMode::Shell
+                },
+                pty,
+                text_only,
+                native_locale_rerun: native_locale,
+                ..Default::default()
+            };
+            let fixed_command = fix::fix_command(command, expand_command, &shell, options);
             match fixed_command {
-                Ok(cmd) => println!("{cmd}"),
+                Ok(Some(cmd)) => println!("{cmd}"),
+                // Cancelled by the user
+                Ok(None) => std::process::exit(1),
                 Err(e) => panic!("Failed to fix command: {e}"),
             }
         }
-        Command::Setup { name } => {
+        Command::Doctor => std::process::exit(if doctor::run(args.shell.as_deref()) {
+            0
+        } else {
+            1
+        }),
+        Command::History { limit, clear } => {
+            let mut history = fix::History::open().expect("Failed to read the fix history");
+            if clear {
+                history.clear().expect("Failed to delete the fix history");
+                println!("{}", "History cleared.".green());
+                return;
+            }
+            if history.entries().is_empty() {
+                println!("{}", "No fixes chosen yet.".yellow());
+                return;
+            }
+            let now = fix::history_now();
+            let entries = history.entries();
+            for entry in &entries[entries.len().saturating_sub(limit)..] {
+                let age = format!("{:>9}", entry.age(now));
+                match (&entry.chosen, &entry.rule) {
+                    (Some(chosen), Some(rule)) => println!(
+                        "{} {} {} {} {}",
+                        age.dark_grey(),
+                        entry.command,
+                        "→".cyan(),
+                        chosen.clone().green(),
+                        format!("({rule})").dark_grey()
+                    ),
+                    _ => println!(
+                        "{} {} {}",
+                        age.dark_grey(),
+                        entry.command,
+                        "(cancelled)".yellow()
+                    ),
+                }
+            }
+        }
+        Command::Instant => match fix::run_instant_session(&shell()) {
+            Ok(code) => std::process::exit(code),
+            Err(e) => match e.kind() {
+                ErrorKind::AlreadyExists => {
+                    eprintln!("{}", "Already running in instant mode.".yellow());
+                    std::process::exit(1);
+                }
+                ErrorKind::Unsupported => {
+                    eprintln!("{}", "Instant mode needs a terminal.".yellow());
+                    std::process::exit(1);
+                }
+                _ => panic!("Failed to start instant mode: {e}"),
+            },
+        },
+        Command::Stats { clear } => {
+            if clear {
+                fix::Stats::clear().expect("Failed to delete the rule statistics");
+                println!("{}", "Statistics cleared.".green());
+                return;
+            }
+            let stats = fix::Stats::open().expect("Failed to read the rule statistics");
+            if stats.rules().is_empty() {
+                println!("{}", "No rules evaluated yet.".yellow());
+                return;
+            }
+            let width = stats
+                .rules()
+                .keys()
+                .map(String::len)
+                .max()
+                .unwrap_or_default()
+                .max("Rule".len());
+            println!(
+                "{}",
+                format!(
+                    "{:<width$} {:>6} {:>8} {:>9} {:>10} {:>7}",
+                    "Rule", "Runs", "Matched", "Accepted", "Avg time", "Errors"
+                )
+                .bold()
+            );
+            for (rule, rule_stats) in stats.rules() {
+                let errors = format!("{:>7}", rule_stats.errors);
+                println!(
+                    "{rule:<width$} {:>6} {:>8} {:>9} {:>10} {}",
+                    rule_stats.runs,
+                    rule_stats.matches,
+                    rule_stats.accepts,
+                    format!("{:.1?}", rule_stats.average_time()),
+                    if rule_stats.errors > 0 {
+                        errors.red()
+                    } else {
+                        errors.stylize()
+                    }
+                );
+            }
+        }
+        Command::Uninstall {
+            purge,
+            dry_run,
+            rc_file,
+        } => uninstall(purge, dry_run, rc_file),
+        Command::Setup {
+            name,
+            replace_history,
+            key_binding,
+            rc_file,
+        } => {
+            let shell = shell();
             let program_path =
                 env::current_exe().expect("Could not determine the current executable path.");
-            match shell.setup_alias(&name, program_path.as_path()) {
+            let options = shells::AliasOptions {
+                replace_history,
+                key_binding,
+            };
+            reject_unsupported_options(&shell, options);
+            match shell.setup_alias(&name, program_path.as_path(), options, rc_file.as_deref()) {
                 Ok(_) => println!(
                     "{}",
                     format!("Alias setup successfully for {shell:?} as {name}").green()
@@ -57,18 +202,24 @@ fn main() {
                     _ => panic!("Failed to set up alias: {e}"),
                 },
             }
-            match dirs::config_dir()
-                .ok_or(ErrorKind::NotFound.into())
-                .and_then(|dir| misc::create_default_fix_rules(dir.join("theshit/fix_rules")))
-            {
+            let rules_dir = dirs::config_dir()
+                .expect("Could not determine the config directory.")
+                .join("theshit/fix_rules");
+            match misc::create_default_fix_rules(rules_dir.clone()) {
                 Ok(_) => println!("{}", "Default rules setup successfully".green()),
                 Err(e) => match e.kind() {
-                    ErrorKind::AlreadyExists => {
-                        println!(
-                            "{}",
-                            "Default rules already exist, skipping rules setup.".yellow()
-                        );
-                    }
+                    ErrorKind::AlreadyExists => match misc::sync_default_fix_rules(&rules_dir) {
+                        Ok(added) if added.is_empty() => {
+                            println!("{}", "Default rules are up to date.".yellow())
+                        }
+                        Ok(added) => {
+                            println!("{}", "Added new default rules:".green());
+                            for path in added {
+                                println!("  {}", path.display());
+                            }
+                        }
+                        Err(e) => panic!("Failed to update default rules: {e}"),
+                    },
                     _ => {
                         panic!("Failed to set up default rules: {e}");
                     }
@@ -77,3 +228,62 @@ fn main() {
         }
     }
 }
+
+/// Exits when the options were given for a shell whose function would silently ignore them.
+fn reject_unsupported_options(shell: &shells::Shell, options: shells::AliasOptions) {
+    if !shell.supports_alias_options() && !options.arguments().is_empty() {
+        eprintln!(
+            "{}",
+            format!(
+                "Not supported in {shell:?}, only in bash, zsh and fish:{}",
+                options.arguments()
+            )
+            .red()
+        );
+        std::process::exit(1);
+    }
+}
+
+fn uninstall(purge: bool, dry_run: bool, rc_file: Option<PathBuf>) {
+    let mut removals = Vec::new();
+    for target in shells::Shell::iter() {
+        match target.remove_setup(dry_run) {
+            Ok(target_removals) => removals.extend(target_removals),
+            Err(e) => eprintln!(
+                "{}: {e}",
+                format!("Failed to remove the {target:?} setup").red()
+            ),
+        }
+    }
+    if let Some(rc_file) = rc_file {
+        match shells::remove_setup_from(&rc_file, dry_run) {
+            Ok(rc_file_removals) => removals.extend(rc_file_removals),
+            Err(e) => eprintln!(
+                "{}: {e}",
+                format!("Failed to remove the setup from {}", rc_file.display()).red()
+            ),
+        }
+    }
+    if let Some(config_dir) = dirs::config_dir().map(|dir| dir.join("theshit"))
+        && purge
+        && config_dir.exists()
+    {
+        if !dry_run {
+            std::fs::remove_dir_all(&config_dir)
+                .expect("Failed to delete the configuration directory");
+        }
+        removals.push(shells::Removal {
+            path: config_dir,
+            line: None,
+        });
+    }
+    if removals.is_empty() {
+        println!("{}", "Nothing to remove.".yellow());
+    } else {
+        let header = if dry_run { "Would remove:" } else { "Removed:" };
+        println!("{}", header.green());
+        for removal in removals {
+            println!("  {removal}");
+        }
+    }
+}
@@ -3,12 +3,15 @@ use crossterm::style::Stylize;
 use include_dir::{Dir, DirEntry, include_dir};
 use regex::Regex;
 use std::cmp::{max, min};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::{ErrorKind, Result};
 use std::path::{Path, PathBuf};
 
 static ASSETS_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/assets");
+/// Records the version and the built-in rules applied to the rules directory, one per line after the version.
+pub const APPLIED_RULES_FILE: &str = ".applied";
+const RULE_GROUPS: [&str; 2] = ["active", "additional"];
 
 #[cfg(not(feature = "standard_panic"))]
 pub fn set_panic_hook() {
@@ -16,7 +19,7 @@ pub fn set_panic_hook() {
         let msg = info
             .payload()
             .downcast_ref::<&str>()
-            .map(|s| *s)
+            .copied()
             .or_else(|| info.payload().downcast_ref::<String>().map(|s| &**s))
             .unwrap_or("Unknown panic");
         eprintln!("Panic occurred: {}", msg.red());
@@ -60,16 +63,93 @@ pub fn create_default_fix_rules(rules_dir: PathBuf) -> Result<()> {
             .expect("Active rules didn't find"),
         &rules_dir,
     )?;
-    Ok(())
+    write_applied_rules(&rules_dir)
+}
+
+fn default_rules() -> impl Iterator<Item = (&'static str, &'static include_dir::File<'static>)> {
+    RULE_GROUPS.into_iter().flat_map(|group| {
+        ASSETS_DIR
+            .get_dir(format!("rules/{group}"))
+            .into_iter()
+            .flat_map(|dir| dir.files())
+            .filter(|file| file.path().file_name().unwrap_or_default() != ".gitkeep")
+            .map(move |file| (group, file))
+    })
+}
+
+fn write_applied_rules(rules_dir: &Path) -> Result<()> {
+    let mut content = format!("{}\n", env!("CARGO_PKG_VERSION"));
+    for (_, file) in default_rules() {
+        content.push_str(&format!("{}\n", file.path().file_name().unwrap().display()));
+    }
+    fs::write(rules_dir.join(APPLIED_RULES_FILE), content)
+}
+
+/// Adds the built-in rules added since the rules were last applied, in the group they ship in.
+/// Rules the user disabled, moved to another group or deleted stay that way.
+pub fn sync_default_fix_rules(rules_dir: &Path) -> Result<Vec<PathBuf>> {
+    let applied: HashSet<String> = match fs::read_to_string(rules_dir.join(APPLIED_RULES_FILE)) {
+        Ok(content) => content.lines().skip(1).map(str::to_string).collect(),
+        Err(e) if e.kind() == ErrorKind::NotFound => HashSet::new(),
+        Err(e) => return Err(e),
+    };
+    let mut added = Vec::new();
+    for (group, file) in default_rules() {
+        let name = file.path().file_name().unwrap();
+        let name_str = name.to_string_lossy();
+        let is_present = RULE_GROUPS.iter().any(|user_group| {
+            let dir = rules_dir.join(user_group);
+            dir.join(name).exists() || dir.join(format!("{name_str}.bak")).exists()
+        });
+        if applied.contains(name_str.as_ref()) || is_present {
+            continue;
+        }
+        let path = rules_dir.join(group).join(name);
+        fs::create_dir_all(rules_dir.join(group))?;
+        fs::write(&path, file.contents())?;
+        added.push(path);
+    }
+    write_applied_rules(rules_dir)?;
+    Ok(added)
 }
 
 pub fn expand_aliases(command: &str, aliases: HashMap<String, String>) -> String {
-    let binary = command.split(' ').next().expect("Could not find binary");
+    let mut command = command.to_string();
+    // An alias can expand to another alias, each one is expanded only once
+    // so self-referencing aliases like `ls='ls --color'` don't loop forever
+    let mut expanded = HashSet::new();
+    loop {
+        let binary = command.split(' ').next().expect("Could not find binary");
+        match aliases.get(binary) {
+            Some(value) if expanded.insert(binary.to_string()) => {
+                command = command.replacen(binary, value, 1);
+            }
+            _ => return command,
+        }
+    }
+}
 
-    if aliases.contains_key(binary) {
-        command.replacen(binary, &aliases[binary], 1)
-    } else {
-        command.to_string()
+/// Expands aliases that apply to any word of the command, like zsh global aliases.
+pub fn expand_global_aliases(command: &str, aliases: &HashMap<String, String>) -> String {
+    if aliases.is_empty() {
+        return command.to_string();
+    }
+    command
+        .split(' ')
+        .map(|word| aliases.get(word).map_or(word, String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Expands zsh suffix aliases, where `file.txt` runs as `<alias value> file.txt`.
+pub fn expand_suffix_alias(command: &str, aliases: &HashMap<String, String>) -> String {
+    let binary = command.split(' ').next().unwrap_or_default();
+    match binary
+        .rsplit_once('.')
+        .and_then(|(_, suffix)| aliases.get(suffix))
+    {
+        Some(value) => format!("{value} {command}"),
+        None => command.to_string(),
     }
 }
 
@@ -118,6 +198,47 @@ pub fn split_command(command: &str) -> Vec<String> {
         .unwrap_or(command.split_whitespace().map(|s| s.to_string()).collect())
 }
 
+/// Removes ANSI escape sequences: CSI sequences like colours and cursor movement,
+/// OSC sequences like window titles and hyperlinks, and short escapes like charset switches.
+pub fn strip_ansi_escapes(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI ends with a byte in the @..~ range
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC ends with BEL or ESC \
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // Character set designations take one more byte
+            Some('(' | ')' | '*' | '+') => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
 pub fn replace_argument(script: &str, from: &str, to: &str) -> String {
     let end_pattern = format!(r" {}$", regex::escape(from));
     let end_regex = Regex::new(&end_pattern).unwrap();
@@ -154,6 +275,20 @@ mod tests {
         assert_eq!(split_command(""), Vec::<String>::new());
     }
 
+    #[test]
+    fn test_strip_ansi_escapes() {
+        assert_eq!(
+            strip_ansi_escapes("\x1b[1;31merror\x1b[0m: not found"),
+            "error: not found"
+        );
+        assert_eq!(
+            strip_ansi_escapes("\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x1b\\"),
+            "link"
+        );
+        assert_eq!(strip_ansi_escapes("\x1b]0;title\x07text\x1b(B"), "text");
+        assert_eq!(strip_ansi_escapes("plain text"), "plain text");
+    }
+
     #[test]
     fn test_replace_argument() {
         let script = "echo hello world";
@@ -193,6 +328,38 @@ mod tests {
         assert_eq!(result.unwrap_err().kind(), ErrorKind::AlreadyExists);
     }
 
+    #[test]
+    fn sync_adds_rules_missing_from_old_setups() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let rules_dir = temp_dir.path().join("fix_rules");
+        fs::create_dir_all(rules_dir.join("active")).unwrap();
+        fs::create_dir_all(rules_dir.join("additional")).unwrap();
+        fs::write(rules_dir.join("active/sudo.native.bak"), "").unwrap();
+        fs::write(rules_dir.join("additional/to_cd.native"), "").unwrap();
+
+        let added = sync_default_fix_rules(&rules_dir).unwrap();
+        assert!(added.contains(&rules_dir.join("active/mkdir_p.native")));
+        assert!(rules_dir.join("active/mkdir_p.native").exists());
+        // Disabled and moved rules stay where the user put them
+        assert!(!rules_dir.join("active/sudo.native").exists());
+        assert!(!rules_dir.join("active/to_cd.native").exists());
+
+        let applied = fs::read_to_string(rules_dir.join(APPLIED_RULES_FILE)).unwrap();
+        assert_eq!(applied.lines().next(), Some(env!("CARGO_PKG_VERSION")));
+        assert!(applied.lines().any(|line| line == "mkdir_p.native"));
+    }
+
+    #[test]
+    fn sync_keeps_deleted_rules_deleted() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let rules_dir = temp_dir.path().join("fix_rules");
+        create_default_fix_rules(rules_dir.clone()).unwrap();
+        fs::remove_file(rules_dir.join("active/mkdir_p.native")).unwrap();
+
+        assert!(sync_default_fix_rules(&rules_dir).unwrap().is_empty());
+        assert!(!rules_dir.join("active/mkdir_p.native").exists());
+    }
+
     fn get_mock_alias() -> HashMap<String, String> {
         let mut aliases = HashMap::new();
         aliases.insert("ll".to_string(), "ls -l".to_string());
@@ -251,6 +418,47 @@ mod tests {
         assert_eq!(result, "echo test again");
     }
 
+    #[test]
+    fn test_expand_alias_recursively() {
+        let mut aliases = HashMap::new();
+        aliases.insert("g".to_string(), "git".to_string());
+        aliases.insert("gs".to_string(), "g status".to_string());
+
+        let result = expand_aliases("gs --short", aliases);
+        assert_eq!(result, "git status --short");
+    }
+
+    #[test]
+    fn test_expand_self_referencing_alias() {
+        let mut aliases = HashMap::new();
+        aliases.insert("ls".to_string(), "ls --color=auto".to_string());
+
+        let result = expand_aliases("ls -l", aliases);
+        assert_eq!(result, "ls --color=auto -l");
+    }
+
+    #[test]
+    fn test_expand_global_aliases() {
+        let mut aliases = HashMap::new();
+        aliases.insert("G".to_string(), "| grep".to_string());
+
+        let result = expand_global_aliases("ls -l G txt", &aliases);
+        assert_eq!(result, "ls -l | grep txt");
+        assert_eq!(expand_global_aliases("ls Gx", &aliases), "ls Gx");
+    }
+
+    #[test]
+    fn test_expand_suffix_alias() {
+        let mut aliases = HashMap::new();
+        aliases.insert("txt".to_string(), "vim".to_string());
+
+        assert_eq!(expand_suffix_alias("notes.txt", &aliases), "vim notes.txt");
+        assert_eq!(
+            expand_suffix_alias("cat notes.txt", &aliases),
+            "cat notes.txt"
+        );
+    }
+
     #[test]
     fn test_damerau_levenshtein_distance_identical_strings() {
         assert_eq!(damerau_levenshtein_distance("hello", "hello"), 0);
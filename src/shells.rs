@@ -1,9 +1,18 @@
 mod bash;
+mod elvish;
 mod enums;
 mod fish;
 mod generic;
 mod helpers;
+mod nushell;
+mod powershell;
+mod tcsh;
+mod xonsh;
 mod zsh;
 
 pub use enums::Shell;
-pub use helpers::get_current_shell;
+pub use generic::{AliasOptions, INTEGRATION_VARIABLES, Removal, remove_setup_from};
+pub use helpers::{
+    get_current_shell, get_current_shell_by_env, get_current_shell_by_process, get_previous_cwd,
+    get_previous_pipe_status,
+};
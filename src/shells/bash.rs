@@ -1,36 +1,129 @@
-use crate::shells::generic;
+use crate::shells::generic::{self, AliasOptions, Removal};
 use std::collections::HashMap;
 use std::env;
 use std::io::ErrorKind;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-pub fn get_shell_function(name: &str, path: &Path) -> String {
+/// The setup evaluates this unquoted, so it ends up on one line: no comments,
+/// every statement ends with `;`. `PIPESTATUS` is read first, every command overwrites it,
+/// and in instant mode the hooks mark where the output of each command starts and ends.
+/// PS0 expands before the command runs, when `fc -ln -0` is already the command.
+/// The prompt hook keeps the directory the last command started in, it moves on only
+/// with the history like `fc -ln -1` does.
+pub fn get_shell_function(name: &str, path: &Path, options: AliasOptions) -> String {
+    // `history -s` replaces the last entry, the alias call; `history -r` only reads files
+    let push_history = if options.replace_history {
+        "history -s -- \"$SH_CMD\";"
+    } else {
+        "local SH_HISTORY; SH_HISTORY=$(mktemp) && printf '%s\\n' \"$SH_CMD\" > \"$SH_HISTORY\" && history -r \"$SH_HISTORY\"; rm -f \"$SH_HISTORY\";"
+    };
+    // `fc -ln -0` is the last entry, outside of a running command there is no alias call to skip.
+    // The command on the line hasn't run, it is fixed by its text alone
+    let key_binding = if options.key_binding {
+        format!(
+            "
+__theshit_widget() {{
+    local SH_CMD;
+    SH_CMD=$(
+      export SH_SHELL=bash;
+      if [ -n \"$READLINE_LINE\" ]; then
+        export SH_PREV_CMD=\"$READLINE_LINE\";
+        export SH_PREV_CWD=\"$PWD\";
+        set -- --text-only;
+      else
+        export SH_PREV_CMD=\"$(fc -ln -0)\";
+        export SH_PREV_CWD=\"${{__theshit_prev_cwd:-$PWD}}\";
+      fi;
+      export SH_SHELL_ALIASES=\"$(alias)\";
+      export SH_SHELL_FUNCTIONS=\"$(compgen -A function)\";
+      command {} fix \"$@\"
+    ) && READLINE_LINE=\"$SH_CMD\" && READLINE_POINT=${{#SH_CMD}};
+}};
+bind -x '\"\\e\\e\": __theshit_widget';",
+            path.display()
+        )
+    } else {
+        String::new()
+    };
     format!(
         "
 {name}() {{
+    export SH_PREV_PIPESTATUS=\"${{PIPESTATUS[*]}}\";
     export SH_SHELL=bash;
     export SH_PREV_CMD=\"$(fc -ln -1)\";
+    export SH_PREV_CWD=\"${{__theshit_prev_cwd:-$PWD}}\";
     export SH_SHELL_ALIASES=\"$(alias)\";
+    export SH_SHELL_FUNCTIONS=\"$(compgen -A function)\";
     
     local SH_CMD;
     SH_CMD=$(
       command {} fix \"$@\"
-    ) && eval \"$SH_CMD\";
+    ) && {{ {push_history} eval \"$SH_CMD\"; }};
 
+    unset SH_SHELL_FUNCTIONS;
     unset SH_SHELL_ALIASES;
+    unset SH_PREV_CWD;
     unset SH_PREV_CMD;
     unset SH_SHELL;
-}}
+    unset SH_PREV_PIPESTATUS;
+}};
+
+__theshit_track_cwd() {{ local exit_code=$?; if [[ \"$HISTCMD\" != \"$__theshit_histcmd\" ]]; then __theshit_histcmd=$HISTCMD; __theshit_prev_cwd=$__theshit_cwd; fi; __theshit_cwd=$PWD; return $exit_code; }};
+if [[ \"$PROMPT_COMMAND\" != *__theshit_track_cwd* ]]; then
+    PROMPT_COMMAND=\"__theshit_track_cwd${{PROMPT_COMMAND:+;$PROMPT_COMMAND}}\";
+fi;
+if [ -n \"$SH_INSTANT_LOG\" ] && [[ \"$PROMPT_COMMAND\" != *__theshit_instant_end* ]]; then
+    __theshit_instant_begin() {{ local c; c=\"$(fc -ln -0)\"; printf '\\033]6973;B;%s\\007' \"${{c//[[:cntrl:]]/ }}\"; }};
+    __theshit_instant_end() {{ local exit_code=$?; printf '\\033]6973;E\\007'; return $exit_code; }};
+    PS0=\"${{PS0}}\"'$(__theshit_instant_begin)';
+    PROMPT_COMMAND=\"__theshit_instant_end${{PROMPT_COMMAND:+;$PROMPT_COMMAND}}\";
+fi;
+{key_binding}
     ",
         path.display()
     )
 }
 
-pub fn setup_alias(name: &str, program_path: &Path) -> std::io::Result<()> {
-    let config_path = dirs::home_dir().ok_or(ErrorKind::NotFound)?.join(".bashrc");
+/// `.bashrc`, unless only `.bash_profile` exists, like on macOS where terminals start login shells.
+pub fn config_path() -> std::io::Result<PathBuf> {
+    let home_dir = dirs::home_dir().ok_or(ErrorKind::NotFound)?;
+    let bashrc = home_dir.join(".bashrc");
+    let bash_profile = home_dir.join(".bash_profile");
+    Ok(if !bashrc.exists() && bash_profile.exists() {
+        bash_profile
+    } else {
+        bashrc
+    })
+}
+
+/// The setup may be in either file, depending on which existed back then.
+pub fn remove_setup(dry_run: bool) -> std::io::Result<Vec<Removal>> {
+    let home_dir = dirs::home_dir().ok_or(ErrorKind::NotFound)?;
+    let mut removals = Vec::new();
+    for config_path in [home_dir.join(".bashrc"), home_dir.join(".bash_profile")] {
+        removals.extend(generic::remove_setup(
+            &config_path,
+            generic::is_setup_line,
+            dry_run,
+        )?);
+    }
+    Ok(removals)
+}
+
+pub fn setup_alias(
+    name: &str,
+    program_path: &Path,
+    options: AliasOptions,
+    config_path: &Path,
+) -> std::io::Result<()> {
     generic::setup_alias(
-        format!("eval $( {} alias {})", program_path.display(), name),
-        config_path.as_path(),
+        format!(
+            "eval $( {} alias {}{})",
+            program_path.display(),
+            name,
+            options.arguments()
+        ),
+        config_path,
     )
 }
 
@@ -58,6 +151,15 @@ pub fn get_aliases() -> HashMap<String, String> {
     aliases
 }
 
+/// The `alias` output is valid bash, aliases just have to be enabled for `bash -c`.
+pub fn get_alias_definitions() -> String {
+    let raw_aliases = env::var("SH_SHELL_ALIASES").unwrap_or(String::from(""));
+    if raw_aliases.trim().is_empty() {
+        return String::new();
+    }
+    format!("shopt -s expand_aliases\n{raw_aliases}")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -66,24 +168,105 @@ mod tests {
     #[test]
     fn test_get_shell_function_contains_name() {
         let path = PathBuf::from("/usr/bin/theshit");
-        let result = get_shell_function("shit", &path);
+        let result = get_shell_function("shit", &path, AliasOptions::default());
         assert!(result.contains("shit()"));
     }
 
     #[test]
     fn test_get_shell_function_contains_path() {
         let path = PathBuf::from("/usr/bin/theshit");
-        let result = get_shell_function("shit", &path);
+        let result = get_shell_function("shit", &path, AliasOptions::default());
         assert!(result.contains("/usr/bin/theshit"));
     }
 
     #[test]
     fn test_get_shell_function_exports_shell_type() {
         let path = PathBuf::from("/usr/bin/theshit");
-        let result = get_shell_function("shit", &path);
+        let result = get_shell_function("shit", &path, AliasOptions::default());
         assert!(result.contains("export SH_SHELL=bash"));
     }
 
+    // The setup runs `eval $(theshit alias)` unquoted, which puts the function on one line.
+    // bash parses the zsh function as well, so it checks both.
+    #[test]
+    fn test_get_shell_function_evaluates_on_one_line() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let replace_history = AliasOptions {
+            replace_history: true,
+            ..Default::default()
+        };
+        let key_binding = AliasOptions {
+            key_binding: true,
+            ..Default::default()
+        };
+        for options in [AliasOptions::default(), replace_history, key_binding] {
+            let result = get_shell_function("shit", &path, options);
+            assert!(
+                !result
+                    .lines()
+                    .any(|line| line.trim_start().starts_with('#'))
+            );
+            // Commands of the other shell may fail, only the syntax matters
+            let output = std::process::Command::new("bash")
+                .args([
+                    "--norc",
+                    "-c",
+                    "eval $(printf '%s' \"$1\"); declare -F shit",
+                ])
+                .arg("bash")
+                .arg(&result)
+                .env_remove("SH_INSTANT_LOG")
+                .output()
+                .unwrap();
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(!stderr.contains("syntax error"), "{stderr}");
+            assert_eq!(String::from_utf8_lossy(&output.stdout), "shit\n");
+        }
+    }
+
+    #[test]
+    fn test_get_shell_function_pushes_history() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path, AliasOptions::default());
+        assert!(result.contains("history -r"));
+        let options = AliasOptions {
+            replace_history: true,
+            ..Default::default()
+        };
+        let result = get_shell_function("shit", &path, options);
+        assert!(result.contains("history -s -- \"$SH_CMD\""));
+    }
+
+    #[test]
+    fn test_get_shell_function_key_binding() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path, AliasOptions::default());
+        assert!(!result.contains("__theshit_widget"));
+        let options = AliasOptions {
+            key_binding: true,
+            ..Default::default()
+        };
+        let result = get_shell_function("shit", &path, options);
+        assert!(result.contains("bind -x"));
+    }
+
+    #[test]
+    fn test_get_shell_function_marks_instant_mode_output() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path, AliasOptions::default());
+        assert!(result.contains("PS0="));
+        assert!(result.contains("__theshit_instant_begin"));
+        assert!(result.contains("__theshit_instant_end"));
+    }
+
+    #[test]
+    fn test_get_shell_function_tracks_previous_cwd() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path, AliasOptions::default());
+        assert!(result.contains("PROMPT_COMMAND=\"__theshit_track_cwd"));
+        assert!(result.contains("SH_PREV_CWD=\"${__theshit_prev_cwd:-$PWD}\""));
+    }
+
     #[test]
     fn test_get_aliases_empty() {
         let aliases = get_aliases();
@@ -0,0 +1,110 @@
+use crate::shells::generic;
+use std::collections::HashMap;
+use std::env;
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+/// Elvish has no aliases, so the command abbreviations from `$edit:abbr` are exported instead.
+/// Definitions made by `eval` don't leak into the caller, hence the `edit:add-var`.
+pub fn get_shell_function(name: &str, path: &Path) -> String {
+    format!(
+        "
+use str
+fn {name} {{|@args|
+    set-env SH_SHELL elvish
+    # The newest history entry is the call of this function itself
+    set-env SH_PREV_CMD [(edit:command-history &cmd-only &newest-first | take 2)][1]
+    set-env SH_PREV_CWD $pwd
+    set-env SH_SHELL_ALIASES (str:join \"\\n\" [(keys $edit:abbr | each {{|abbr| put $abbr\"\\t\"$edit:abbr[$abbr] }})])
+
+    var sh-cmd = ''
+    try {{
+        set sh-cmd = ((external '{}') fix $@args | slurp)
+    }} catch {{
+        nop
+    }} finally {{
+        unset-env SH_SHELL_ALIASES
+        unset-env SH_PREV_CWD
+        unset-env SH_PREV_CMD
+        unset-env SH_SHELL
+    }}
+    if (not-eq $sh-cmd '') {{
+        eval $sh-cmd
+    }}
+}}
+edit:add-var {name}~ ${name}~
+    ",
+        path.display()
+    )
+    .trim()
+    .to_string()
+}
+
+pub fn config_path() -> Result<PathBuf> {
+    Ok(dirs::config_dir()
+        .ok_or(ErrorKind::NotFound)?
+        .join("elvish/rc.elv"))
+}
+
+pub fn setup_alias(name: &str, program_path: &Path, config_path: &Path) -> Result<()> {
+    generic::setup_alias(
+        format!(
+            "eval ((external '{}') alias {} | slurp)",
+            program_path.display(),
+            name
+        ),
+        config_path,
+    )
+}
+
+pub fn get_aliases() -> HashMap<String, String> {
+    let raw_aliases = env::var("SH_SHELL_ALIASES").unwrap_or(String::from(""));
+    generic::parse_tab_separated_aliases(&raw_aliases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_get_shell_function_contains_name() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path);
+        assert!(result.contains("fn shit {|@args|"));
+        assert!(result.contains("edit:add-var shit~ $shit~"));
+    }
+
+    #[test]
+    fn test_get_shell_function_contains_path() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path);
+        assert!(result.contains("(external '/usr/bin/theshit') fix $@args"));
+    }
+
+    #[test]
+    fn test_get_shell_function_exports_shell_type() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path);
+        assert!(result.contains("set-env SH_SHELL elvish"));
+    }
+
+    #[test]
+    fn test_get_aliases_empty() {
+        let aliases = get_aliases();
+        assert!(aliases.is_empty());
+    }
+
+    #[test]
+    fn test_get_aliases_with_env() {
+        unsafe {
+            env::set_var("SH_SHELL_ALIASES", "gst\tgit status\nll\tls -l");
+        }
+        let aliases = get_aliases();
+        assert_eq!(aliases.get("gst"), Some(&"git status".to_string()));
+        assert_eq!(aliases.get("ll"), Some(&"ls -l".to_string()));
+        unsafe {
+            env::remove_var("SH_SHELL_ALIASES");
+        }
+    }
+}
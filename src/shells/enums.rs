@@ -1,10 +1,14 @@
-use super::{bash, fish, zsh};
-use std::collections::HashMap;
+use super::generic::{AliasOptions, Removal};
+use super::{bash, elvish, fish, generic, nushell, powershell, tcsh, xonsh, zsh};
+use crate::misc;
+use std::collections::{HashMap, HashSet};
 use std::io::Result;
-use std::path::Path;
-use strum::EnumString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use strum::{AsRefStr, EnumIter, EnumString};
 
-#[derive(EnumString, Debug)]
+#[derive(EnumString, AsRefStr, EnumIter, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Shell {
     #[strum(serialize = "bash")]
     Bash,
@@ -14,21 +18,84 @@ pub enum Shell {
 
     #[strum(serialize = "fish")]
     Fish,
+
+    #[strum(serialize = "nu", serialize = "nushell")]
+    Nushell,
+
+    #[strum(serialize = "pwsh", serialize = "powershell")]
+    PowerShell,
+
+    #[strum(serialize = "xonsh")]
+    Xonsh,
+
+    #[strum(serialize = "elvish")]
+    Elvish,
+
+    #[strum(serialize = "tcsh")]
+    Tcsh,
 }
 
 impl Shell {
-    pub fn get_shell_function(&self, name: &str, path: &Path) -> String {
+    pub fn get_shell_function(&self, name: &str, path: &Path, options: AliasOptions) -> String {
+        match self {
+            Shell::Bash => bash::get_shell_function(name, path, options),
+            Shell::Zsh => zsh::get_shell_function(name, path, options),
+            Shell::Fish => fish::get_shell_function(name, path, options),
+            Shell::Nushell => nushell::get_shell_function(name, path),
+            Shell::PowerShell => powershell::get_shell_function(name, path),
+            Shell::Xonsh => xonsh::get_shell_function(name, path),
+            Shell::Elvish => elvish::get_shell_function(name, path),
+            Shell::Tcsh => tcsh::get_shell_function(name, path),
+        }
+    }
+    /// Whether the shell function honours `AliasOptions`, the other shells would ignore them.
+    pub fn supports_alias_options(&self) -> bool {
+        matches!(self, Shell::Bash | Shell::Zsh | Shell::Fish)
+    }
+    /// The file the setup goes to by default.
+    pub fn config_path(&self) -> Result<PathBuf> {
+        match self {
+            Shell::Bash => bash::config_path(),
+            Shell::Zsh => zsh::config_path(),
+            Shell::Fish => fish::config_path(),
+            Shell::Nushell => nushell::config_path(),
+            Shell::PowerShell => powershell::config_path(),
+            Shell::Xonsh => xonsh::config_path(),
+            Shell::Elvish => elvish::config_path(),
+            Shell::Tcsh => tcsh::config_path(),
+        }
+    }
+    /// Sets the alias up in `rc_file`, or in the shell's usual config file without one.
+    pub fn setup_alias(
+        &self,
+        name: &str,
+        path: &Path,
+        options: AliasOptions,
+        rc_file: Option<&Path>,
+    ) -> Result<()> {
+        let config_path = match rc_file {
+            Some(rc_file) => rc_file.to_path_buf(),
+            None => self.config_path()?,
+        };
         match self {
-            Shell::Bash => bash::get_shell_function(name, path),
-            Shell::Zsh => zsh::get_shell_function(name, path),
-            Shell::Fish => fish::get_shell_function(name, path),
+            Shell::Bash => bash::setup_alias(name, path, options, &config_path),
+            Shell::Zsh => zsh::setup_alias(name, path, options, &config_path),
+            Shell::Fish => fish::setup_alias(name, path, options, &config_path),
+            Shell::Nushell => nushell::setup_alias(name, path, &config_path),
+            Shell::PowerShell => powershell::setup_alias(name, path, &config_path),
+            Shell::Xonsh => xonsh::setup_alias(name, path, &config_path),
+            Shell::Elvish => elvish::setup_alias(name, path, &config_path),
+            Shell::Tcsh => tcsh::setup_alias(name, path, &config_path),
         }
     }
-    pub fn setup_alias(&self, name: &str, path: &Path) -> Result<()> {
+    /// Undoes `setup_alias` in the shell's usual config files, with `dry_run` only reports
+    /// what would be removed.
+    pub fn remove_setup(&self, dry_run: bool) -> Result<Vec<Removal>> {
         match self {
-            Shell::Bash => bash::setup_alias(name, path),
-            Shell::Zsh => zsh::setup_alias(name, path),
-            Shell::Fish => fish::setup_alias(name, path),
+            Shell::Bash => bash::remove_setup(dry_run),
+            Shell::Fish => fish::remove_setup(dry_run),
+            Shell::Nushell => nushell::remove_setup(dry_run),
+            _ => generic::remove_setup(&self.config_path()?, generic::is_setup_line, dry_run),
         }
     }
     pub fn get_aliases(&self) -> HashMap<String, String> {
@@ -36,6 +103,99 @@ impl Shell {
             Shell::Bash => bash::get_aliases(),
             Shell::Zsh => zsh::get_aliases(),
             Shell::Fish => fish::get_aliases(),
+            Shell::Nushell => nushell::get_aliases(),
+            Shell::PowerShell => powershell::get_aliases(),
+            Shell::Xonsh => xonsh::get_aliases(),
+            Shell::Elvish => elvish::get_aliases(),
+            Shell::Tcsh => tcsh::get_aliases(),
+        }
+    }
+    /// Aliases expanded anywhere in the command, like zsh global aliases.
+    pub fn get_global_aliases(&self) -> HashMap<String, String> {
+        match self {
+            Shell::Zsh => zsh::get_global_aliases(),
+            Shell::Fish => fish::get_global_aliases(),
+            _ => HashMap::new(),
+        }
+    }
+    /// Aliases keyed by file suffix, only zsh has them.
+    pub fn get_suffix_aliases(&self) -> HashMap<String, String> {
+        match self {
+            Shell::Zsh => zsh::get_suffix_aliases(),
+            _ => HashMap::new(),
+        }
+    }
+    pub fn get_functions(&self) -> HashSet<String> {
+        match self {
+            Shell::Elvish | Shell::Tcsh => HashSet::new(),
+            _ => generic::get_functions(),
+        }
+    }
+    pub fn expand_command(&self, command: &str) -> String {
+        let command = misc::expand_aliases(command, self.get_aliases());
+        let command = misc::expand_suffix_alias(&command, &self.get_suffix_aliases());
+        misc::expand_global_aliases(&command, &self.get_global_aliases())
+    }
+    /// Name of the shell's executable.
+    pub fn program(&self) -> &'static str {
+        match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+            Shell::Nushell => "nu",
+            Shell::PowerShell => "pwsh",
+            Shell::Xonsh => "xonsh",
+            Shell::Elvish => "elvish",
+            Shell::Tcsh => "tcsh",
+        }
+    }
+    /// Runs `command` through the shell. With `load_config` the user's configuration is loaded,
+    /// so the functions defined there are available, at the cost of a slower start.
+    pub fn run_command(&self, command: &str, load_config: bool) -> Command {
+        let args: &[&str] = match (self, load_config) {
+            (Shell::Bash, false) => &["-c"],
+            (Shell::Bash, true) => &["-ic"],
+            (Shell::Zsh, false) => &["-c"],
+            (Shell::Zsh, true) => &["-ic"],
+            (Shell::Fish, false) => &["--no-config", "-c"],
+            (Shell::Fish, true) => &["-c"],
+            (Shell::Nushell, false) => &["--commands"],
+            (Shell::Nushell, true) => &["--login", "--commands"],
+            (Shell::PowerShell, false) => &["-NoLogo", "-NoProfile", "-Command"],
+            (Shell::PowerShell, true) => &["-NoLogo", "-Command"],
+            (Shell::Xonsh, false) => &["--no-rc", "-c"],
+            (Shell::Xonsh, true) => &["-i", "-c"],
+            (Shell::Elvish, _) => &["-c"],
+            (Shell::Tcsh, false) => &["-f", "-c"],
+            (Shell::Tcsh, true) => &["-c"],
+        };
+        let mut shell_command = Command::new(self.program());
+        shell_command.args(args).arg(command);
+        shell_command
+    }
+    /// Definitions of the exported aliases, for the shells that can redefine them in a script.
+    pub fn get_alias_definitions(&self) -> String {
+        match self {
+            Shell::Bash => bash::get_alias_definitions(),
+            Shell::Zsh => zsh::get_alias_definitions(),
+            Shell::Fish => fish::get_alias_definitions(),
+            _ => String::new(),
+        }
+    }
+    /// Script re-running the command without the user's configuration.
+    /// Where the exported aliases can be redefined, they are, so aliases anywhere
+    /// in the command work; other shells get the already expanded command.
+    pub fn script_with_aliases(
+        &self,
+        command: &str,
+        expand_command: &str,
+        alias_definitions: &str,
+    ) -> String {
+        if alias_definitions.is_empty() {
+            expand_command.to_string()
+        } else {
+            // Aliases only apply to lines after their definition
+            format!("{alias_definitions}\n{command}")
         }
     }
 }
@@ -67,17 +227,89 @@ mod tests {
         assert!(matches!(shell.unwrap(), Shell::Fish));
     }
 
+    #[test]
+    fn test_shell_from_str_nushell() {
+        assert!(matches!(Shell::from_str("nu").unwrap(), Shell::Nushell));
+        assert!(matches!(
+            Shell::from_str("nushell").unwrap(),
+            Shell::Nushell
+        ));
+        assert_eq!(Shell::Nushell.as_ref(), "nushell");
+    }
+
+    #[test]
+    fn test_shell_from_str_powershell() {
+        assert!(matches!(
+            Shell::from_str("pwsh").unwrap(),
+            Shell::PowerShell
+        ));
+        assert!(matches!(
+            Shell::from_str("powershell").unwrap(),
+            Shell::PowerShell
+        ));
+        assert_eq!(Shell::PowerShell.as_ref(), "powershell");
+    }
+
+    #[test]
+    fn test_shell_from_str_xonsh_elvish_tcsh() {
+        assert!(matches!(Shell::from_str("xonsh").unwrap(), Shell::Xonsh));
+        assert!(matches!(Shell::from_str("elvish").unwrap(), Shell::Elvish));
+        assert!(matches!(Shell::from_str("tcsh").unwrap(), Shell::Tcsh));
+    }
+
     #[test]
     fn test_shell_from_str_invalid() {
         let shell = Shell::from_str("invalid");
         assert!(shell.is_err());
     }
 
+    #[test]
+    fn test_get_functions() {
+        assert!(Shell::Tcsh.get_functions().is_empty());
+        assert!(Shell::Elvish.get_functions().is_empty());
+    }
+
+    #[test]
+    fn test_supports_alias_options() {
+        assert!(Shell::Fish.supports_alias_options());
+        assert!(!Shell::Elvish.supports_alias_options());
+        assert!(!Shell::Nushell.supports_alias_options());
+    }
+
+    #[test]
+    fn test_run_command() {
+        let command = Shell::Fish.run_command("mkcd foo", true);
+        assert_eq!(command.get_program(), "fish");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            vec!["-c", "mkcd foo"]
+        );
+        let command = Shell::Bash.run_command("cd foo", false);
+        assert_eq!(command.get_args().collect::<Vec<_>>(), vec!["-c", "cd foo"]);
+    }
+
+    #[test]
+    fn test_script_with_aliases() {
+        assert_eq!(
+            Shell::Bash.script_with_aliases(
+                "ll | head",
+                "ls -l | head",
+                "shopt -s expand_aliases\nalias ll='ls -l'"
+            ),
+            "shopt -s expand_aliases\nalias ll='ls -l'\nll | head"
+        );
+        assert_eq!(
+            Shell::Bash.script_with_aliases("ll | head", "ls -l | head", ""),
+            "ls -l | head"
+        );
+        assert!(Shell::Nushell.get_alias_definitions().is_empty());
+    }
+
     #[test]
     fn test_get_shell_function_bash() {
         let shell = Shell::Bash;
         let path = PathBuf::from("/usr/bin/theshit");
-        let result = shell.get_shell_function("shit", &path);
+        let result = shell.get_shell_function("shit", &path, AliasOptions::default());
         assert!(result.contains("shit()"));
         assert!(result.contains("SH_SHELL=bash"));
     }
@@ -86,7 +318,7 @@ mod tests {
     fn test_get_shell_function_zsh() {
         let shell = Shell::Zsh;
         let path = PathBuf::from("/usr/bin/theshit");
-        let result = shell.get_shell_function("shit", &path);
+        let result = shell.get_shell_function("shit", &path, AliasOptions::default());
         assert!(result.contains("shit()"));
         assert!(result.contains("SH_SHELL=zsh"));
     }
@@ -95,8 +327,26 @@ mod tests {
     fn test_get_shell_function_fish() {
         let shell = Shell::Fish;
         let path = PathBuf::from("/usr/bin/theshit");
-        let result = shell.get_shell_function("shit", &path);
+        let result = shell.get_shell_function("shit", &path, AliasOptions::default());
         assert!(result.contains("function shit"));
         assert!(result.contains("SH_SHELL fish"));
     }
+
+    #[test]
+    fn test_get_shell_function_nushell() {
+        let shell = Shell::Nushell;
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = shell.get_shell_function("shit", &path, AliasOptions::default());
+        assert!(result.contains("def shit"));
+        assert!(result.contains("SH_SHELL: nushell"));
+    }
+
+    #[test]
+    fn test_get_shell_function_powershell() {
+        let shell = Shell::PowerShell;
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = shell.get_shell_function("shit", &path, AliasOptions::default());
+        assert!(result.contains("function shit"));
+        assert!(result.contains("SH_SHELL = 'powershell'"));
+    }
 }
@@ -1,42 +1,154 @@
 use crate::misc;
-use crate::shells::generic;
+use crate::shells::generic::{self, AliasOptions, Removal};
 use std::collections::HashMap;
 use std::env;
+use std::fs;
 use std::io::ErrorKind;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-pub fn get_shell_function(name: &str, path: &Path) -> String {
+pub fn get_shell_function(name: &str, path: &Path, options: AliasOptions) -> String {
+    // Keeps the default of skipping commands that start with a space
+    let skip_history = if options.replace_history {
+        format!(
+            "
+if not functions -q fish_should_add_to_history
+    function fish_should_add_to_history
+        not string match -qr '^(\\s|{name}(\\s|$))' -- $argv[1]
+    end
+end"
+        )
+    } else {
+        String::new()
+    };
+    // The command on the line hasn't run, it is fixed by its text alone
+    let key_binding = if options.key_binding {
+        format!(
+            "
+function __theshit_widget -d \"Correct the command line or the previous command\"
+    set -lx SH_SHELL fish
+    set -lx SH_PREV_CMD (commandline)
+    set -lx SH_PREV_CWD $PWD
+    set -l fix_options --text-only
+    if test -z \"$SH_PREV_CMD\"
+        set SH_PREV_CMD \"$history[1]\"
+        set fix_options
+        set -q __theshit_cwd[1]; and set SH_PREV_CWD $__theshit_cwd
+    end
+    set -lx SH_SHELL_ALIASES (alias | string join \\n)
+    set -lx SH_SHELL_ABBREVIATIONS (abbr --show | string join \\n)
+    set -lx SH_SHELL_FUNCTIONS (functions --names | string join \\n)
+
+    set -l SH_CMD (command {} fix $fix_options </dev/tty)
+    if test -n \"$SH_CMD\"
+        commandline -r -- \"$SH_CMD\"
+    end
+    commandline -f repaint
+end
+bind \\e\\e __theshit_widget",
+            path.display()
+        )
+    } else {
+        String::new()
+    };
     format!(
         "
 function {name} -d \"Correct your previous command\"
+    # Read first, every command overwrites it
+    set -lx SH_PREV_PIPESTATUS $pipestatus
     set -lx SH_SHELL fish
     set -lx SH_PREV_CMD \"$history[1]\"
-    set -lx SH_SHELL_ALIASES (alias)
+    set -lx SH_PREV_CWD $PWD
+    set -q __theshit_prev_cwd[1]; and set SH_PREV_CWD $__theshit_prev_cwd
+    set -lx SH_SHELL_ALIASES (alias | string join \\n)
+    set -lx SH_SHELL_ABBREVIATIONS (abbr --show | string join \\n)
+    set -lx SH_SHELL_FUNCTIONS (functions --names | string join \\n)
     
     set -l SH_CMD;
     command {} fix $argv | read -l SH_CMD;
 
 
     if test -n \"$SH_CMD\"
+        builtin history append -- \"$SH_CMD\" 2>/dev/null
         eval \"$SH_CMD\";
     end
+    set -e SH_SHELL_FUNCTIONS;
+    set -e SH_SHELL_ABBREVIATIONS;
     set -e SH_SHELL_ALIASES;
+    set -e SH_PREV_CWD;
     set -e SH_PREV_CMD;
     set -e SH_SHELL;
+    set -e SH_PREV_PIPESTATUS;
+end
+{skip_history}{key_binding}
+
+# The directory each command starts in, the one before is where the command `{name}` corrects ran
+function __theshit_track_cwd --on-event fish_preexec
+    set -g __theshit_prev_cwd $__theshit_cwd
+    set -g __theshit_cwd $PWD
+end
+
+# Instant mode: mark where the output of each command starts and ends
+if set -q SH_INSTANT_LOG
+    function __theshit_instant_begin --on-event fish_preexec
+        printf '\\e]6973;B;%s\\a' (string replace -ra '[[:cntrl:]]' ' ' -- $argv | string join ' ')
+    end
+    function __theshit_instant_end --on-event fish_postexec
+        printf '\\e]6973;E\\a'
+    end
 end
     ",
         path.display()
     )
 }
 
-pub fn setup_alias(name: &str, program_path: &Path) -> std::io::Result<()> {
-    let config_path = dirs::config_dir()
+/// A `conf.d` snippet of its own, fish sources every file there at startup.
+pub fn config_path() -> std::io::Result<PathBuf> {
+    Ok(dirs::config_dir()
         .ok_or(ErrorKind::NotFound)?
-        .join("fish/config.fish");
-    generic::setup_alias(
-        format!("{} alias {} | source", program_path.display(), name),
-        config_path.as_path(),
-    )
+        .join("fish/conf.d/theshit.fish"))
+}
+
+/// Older versions put the setup into `config.fish`.
+fn legacy_config_path() -> std::io::Result<PathBuf> {
+    Ok(dirs::config_dir()
+        .ok_or(ErrorKind::NotFound)?
+        .join("fish/config.fish"))
+}
+
+pub fn remove_setup(dry_run: bool) -> std::io::Result<Vec<Removal>> {
+    let mut removals =
+        generic::remove_setup(&legacy_config_path()?, generic::is_setup_line, dry_run)?;
+    let config_path = config_path()?;
+    if config_path.exists() {
+        if !dry_run {
+            fs::remove_file(&config_path)?;
+        }
+        removals.push(Removal {
+            path: config_path,
+            line: None,
+        });
+    }
+    Ok(removals)
+}
+
+pub fn setup_alias(
+    name: &str,
+    program_path: &Path,
+    options: AliasOptions,
+    config_path: &Path,
+) -> std::io::Result<()> {
+    let setup_command = format!(
+        "{} alias {}{} | source",
+        program_path.display(),
+        name,
+        options.arguments()
+    );
+    if config_path != self::config_path()? {
+        return generic::setup_alias(setup_command, config_path);
+    }
+    // The snippet is theshit's own file, it is written without asking
+    generic::remove_setup(&legacy_config_path()?, generic::is_setup_line, false)?;
+    generic::write_setup_file(&setup_command, config_path)
 }
 
 pub fn get_aliases() -> HashMap<String, String> {
@@ -53,9 +165,60 @@ pub fn get_aliases() -> HashMap<String, String> {
         }
         aliases.insert(parts[1].to_string(), parts[2].to_string());
     }
+    // Abbreviations expanded only in command position behave like aliases
+    aliases.extend(
+        get_abbreviations()
+            .into_iter()
+            .filter(|(_, (_, anywhere))| !anywhere)
+            .map(|(name, (expansion, _))| (name, expansion)),
+    );
     aliases
 }
 
+/// The `alias` output is valid fish, abbreviations only expand interactively so they're left out.
+pub fn get_alias_definitions() -> String {
+    env::var("SH_SHELL_ALIASES")
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| line.starts_with("alias "))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn get_global_aliases() -> HashMap<String, String> {
+    get_abbreviations()
+        .into_iter()
+        .filter(|(_, (_, anywhere))| *anywhere)
+        .map(|(name, (expansion, _))| (name, expansion))
+        .collect()
+}
+
+fn get_abbreviations() -> HashMap<String, (String, bool)> {
+    parse_abbreviations(&env::var("SH_SHELL_ABBREVIATIONS").unwrap_or(String::from("")))
+}
+
+/// Parses `abbr --show` lines like `abbr -a --position anywhere -- name 'expansion'`,
+/// the flag tells whether the abbreviation expands anywhere in the command.
+fn parse_abbreviations(raw_abbreviations: &str) -> HashMap<String, (String, bool)> {
+    let mut abbreviations = HashMap::new();
+    for raw_abbreviation in raw_abbreviations.split('\n') {
+        let parts = misc::split_command(raw_abbreviation);
+        if parts.first().map(String::as_str) != Some("abbr") {
+            continue;
+        }
+        let Some(separator) = parts.iter().position(|part| part == "--") else {
+            continue;
+        };
+        if let [name, expansion] = &parts[separator + 1..] {
+            let anywhere = parts[..separator]
+                .windows(2)
+                .any(|flag| flag[0] == "--position" && flag[1] == "anywhere");
+            abbreviations.insert(name.to_string(), (expansion.to_string(), anywhere));
+        }
+    }
+    abbreviations
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -64,24 +227,51 @@ mod tests {
     #[test]
     fn test_get_shell_function_contains_name() {
         let path = PathBuf::from("/usr/bin/theshit");
-        let result = get_shell_function("shit", &path);
+        let result = get_shell_function("shit", &path, AliasOptions::default());
         assert!(result.contains("function shit"));
     }
 
     #[test]
     fn test_get_shell_function_contains_path() {
         let path = PathBuf::from("/usr/bin/theshit");
-        let result = get_shell_function("shit", &path);
+        let result = get_shell_function("shit", &path, AliasOptions::default());
         assert!(result.contains("/usr/bin/theshit"));
     }
 
     #[test]
     fn test_get_shell_function_exports_shell_type() {
         let path = PathBuf::from("/usr/bin/theshit");
-        let result = get_shell_function("shit", &path);
+        let result = get_shell_function("shit", &path, AliasOptions::default());
         assert!(result.contains("set -lx SH_SHELL fish"));
     }
 
+    #[test]
+    fn test_get_shell_function_pushes_history() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path, AliasOptions::default());
+        assert!(result.contains("builtin history append -- \"$SH_CMD\""));
+        assert!(!result.contains("fish_should_add_to_history"));
+        let options = AliasOptions {
+            replace_history: true,
+            ..Default::default()
+        };
+        let result = get_shell_function("shit", &path, options);
+        assert!(result.contains("function fish_should_add_to_history"));
+    }
+
+    #[test]
+    fn test_get_shell_function_key_binding() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path, AliasOptions::default());
+        assert!(!result.contains("__theshit_widget"));
+        let options = AliasOptions {
+            key_binding: true,
+            ..Default::default()
+        };
+        let result = get_shell_function("shit", &path, options);
+        assert!(result.contains("bind \\e\\e"));
+    }
+
     #[test]
     fn test_get_aliases_empty() {
         let aliases = get_aliases();
@@ -116,4 +306,17 @@ mod tests {
             env::remove_var("SH_SHELL_ALIASES");
         }
     }
+
+    #[test]
+    fn test_parse_abbreviations() {
+        let abbreviations = parse_abbreviations(
+            "abbr -a -- gco 'git checkout'\nabbr -a --position anywhere -- L '| less'\nnot_an_abbr",
+        );
+        assert_eq!(abbreviations.len(), 2);
+        assert_eq!(
+            abbreviations.get("gco"),
+            Some(&("git checkout".to_string(), false))
+        );
+        assert_eq!(abbreviations.get("L"), Some(&("| less".to_string(), true)));
+    }
 }
@@ -1,10 +1,59 @@
-use std::fs::{File, OpenOptions};
-use std::io::{ErrorKind, Read, Result, Write, stdin};
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io::{ErrorKind, Result, stdin};
+use std::path::{Path, PathBuf};
 
+/// Variables the shell functions set for theshit, they are not part of the user's environment.
+/// `SH_INSTANT_LOG` isn't one of them: without it a re-run interactive shell would start
+/// instant mode again from its config.
+pub const INTEGRATION_VARIABLES: &[&str] = &[
+    "SH_SHELL",
+    "SH_PREV_CMD",
+    "SH_PREV_CWD",
+    "SH_PREV_PIPESTATUS",
+    "SH_SHELL_ALIASES",
+    "SH_SHELL_FUNCTIONS",
+    "SH_SHELL_GLOBAL_ALIASES",
+    "SH_SHELL_SUFFIX_ALIASES",
+    "SH_SHELL_ABBREVIATIONS",
+];
+
+/// Options of the generated shell function, supported by bash, zsh and fish.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AliasOptions {
+    /// Replace the alias call in the history with the chosen command, instead of adding it after
+    pub replace_history: bool,
+    /// Bind Esc Esc to fix the command line, or the previous command when it is empty
+    pub key_binding: bool,
+}
+
+impl AliasOptions {
+    /// Arguments of `theshit alias` that reproduce these options, for the setup line.
+    pub fn arguments(&self) -> String {
+        let mut arguments = String::new();
+        if self.replace_history {
+            arguments.push_str(" --replace-history");
+        }
+        if self.key_binding {
+            arguments.push_str(" --key-binding");
+        }
+        arguments
+    }
+}
+
+/// The setup is written between these lines, so it can be found and replaced later.
+const BLOCK_START: &str = "# >>> theshit >>>";
+const BLOCK_END: &str = "# <<< theshit <<<";
+
+/// Writes the setup command in a marked block of the config file. An existing block is replaced
+/// in place, and setup lines written without a block by older versions are removed.
 pub fn setup_alias(setup_command: String, config_path: &Path) -> Result<()> {
-    let mut config_file = match OpenOptions::new().read(true).append(true).open(config_path) {
-        Ok(file) => file,
+    let config_path = &resolve_symlink(config_path)?;
+    let content = match fs::read_to_string(config_path) {
+        Ok(content) => Some(content),
         Err(error) => match error.kind() {
             ErrorKind::NotFound => {
                 println!(
@@ -16,7 +65,7 @@ pub fn setup_alias(setup_command: String, config_path: &Path) -> Result<()> {
                     .read_line(&mut input)
                     .expect("Error getting user input");
                 if input.trim().eq_ignore_ascii_case("y") || input.trim().is_empty() {
-                    File::create(config_path).expect("Failed to create config file")
+                    None
                 } else {
                     return Err(ErrorKind::NotFound.into());
                 }
@@ -27,12 +76,476 @@ pub fn setup_alias(setup_command: String, config_path: &Path) -> Result<()> {
         },
     };
 
-    let mut config_content = String::new();
+    let current = content.as_deref().unwrap_or_default();
+    let (mut lines, block_position, _) =
+        split_setup(current, |line| line == setup_command || is_setup_line(line));
+    let block = setup_block(&setup_command);
+    match block_position {
+        Some(position) => lines.insert(position, &block),
+        None => {
+            if lines.last().is_some_and(|line| !line.ends_with('\n')) {
+                lines.push("\n");
+            }
+            lines.push(&block);
+        }
+    }
+    let updated = lines.concat();
+    if updated == current {
+        return Err(ErrorKind::AlreadyExists.into());
+    }
+    if content.is_some() {
+        backup(config_path)?;
+    }
+    write_atomically(config_path, &updated)
+}
 
-    config_file.read_to_string(&mut config_content)?;
-    if config_content.contains(&setup_command) {
+/// Writes the setup to a file of its own, like a fish `conf.d` snippet.
+pub fn write_setup_file(setup_command: &str, path: &Path) -> Result<()> {
+    let path = &resolve_symlink(path)?;
+    let block = setup_block(setup_command);
+    if fs::read_to_string(path).is_ok_and(|content| content == block) {
         return Err(ErrorKind::AlreadyExists.into());
     }
+    write_atomically(path, &block)
+}
+
+fn setup_block(setup_command: &str) -> String {
+    format!("{BLOCK_START}\n{setup_command}\n{BLOCK_END}\n")
+}
+
+/// Dotfile managers link config files into place: the link stays and the file it points to
+/// is changed. A link to nothing is an error rather than being replaced by a file.
+fn resolve_symlink(config_path: &Path) -> Result<PathBuf> {
+    match fs::symlink_metadata(config_path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => fs::canonicalize(config_path),
+        _ => Ok(config_path.to_path_buf()),
+    }
+}
+
+/// Splits the config into the lines to keep, the position of the theshit block among them
+/// and the setup lines, inside the block or matching `is_setup_line`.
+fn split_setup(
+    content: &str,
+    is_setup_line: impl Fn(&str) -> bool,
+) -> (Vec<&str>, Option<usize>, Vec<String>) {
+    let mut kept = Vec::new();
+    let mut block_position = None;
+    let mut setup_lines = Vec::new();
+    // Lines of the block being read, from its start marker on
+    let mut open_block: Option<Vec<&str>> = None;
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if trimmed == BLOCK_START {
+            // An end marker belongs to the nearest start marker, an earlier one is a stray line
+            if let Some(stray_lines) = open_block.replace(vec![line]) {
+                keep_lines(stray_lines, &is_setup_line, &mut kept, &mut setup_lines);
+            }
+        } else if trimmed == BLOCK_END && open_block.is_some() {
+            let block_lines = open_block.take().unwrap_or_default();
+            block_position.get_or_insert(kept.len());
+            setup_lines.extend(
+                block_lines[1..]
+                    .iter()
+                    .map(|line| line.trim())
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string),
+            );
+        } else if let Some(block_lines) = &mut open_block {
+            block_lines.push(line);
+        } else {
+            keep_lines(vec![line], &is_setup_line, &mut kept, &mut setup_lines);
+        }
+    }
+    // Without its end the start marker is left alone, rather than taking the rest of the file
+    if let Some(stray_lines) = open_block {
+        keep_lines(stray_lines, &is_setup_line, &mut kept, &mut setup_lines);
+    }
+    (kept, block_position, setup_lines)
+}
+
+fn keep_lines<'a>(
+    lines: Vec<&'a str>,
+    is_setup_line: impl Fn(&str) -> bool,
+    kept: &mut Vec<&'a str>,
+    setup_lines: &mut Vec<String>,
+) {
+    for line in lines {
+        if is_setup_line(line.trim()) {
+            setup_lines.push(line.trim().to_string());
+        } else {
+            kept.push(line);
+        }
+    }
+}
+
+/// Keeps the config file as it was before theshit changed it, next to it.
+fn backup(config_path: &Path) -> Result<()> {
+    let mut backup_name = config_path.file_name().unwrap_or_default().to_os_string();
+    backup_name.push(".theshit.bak");
+    fs::copy(config_path, config_path.with_file_name(backup_name))?;
+    Ok(())
+}
+
+/// Writes a temporary file next to the config and renames it over the config,
+/// so the shell never reads a half written file.
+fn write_atomically(config_path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = config_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut temporary_name = OsString::from(".");
+    temporary_name.push(config_path.file_name().unwrap_or_default());
+    temporary_name.push(".theshit.tmp");
+    let temporary_path = config_path.with_file_name(temporary_name);
+    fs::write(&temporary_path, content)?;
+    if let Ok(metadata) = fs::metadata(config_path) {
+        fs::set_permissions(&temporary_path, metadata.permissions())?;
+    }
+    fs::rename(&temporary_path, config_path)
+}
+
+/// Something removed from the user's files to undo the setup.
+pub struct Removal {
+    pub path: PathBuf,
+    /// The removed line, or `None` when the whole file is deleted
+    pub line: Option<String>,
+}
+
+impl fmt::Display for Removal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.line {
+            Some(line) => write!(f, "{}: {line}", self.path.display()),
+            None => write!(f, "{}", self.path.display()),
+        }
+    }
+}
+
+/// Whether the line runs `theshit alias`, whatever the path of the executable and the alias name.
+pub fn is_setup_line(line: &str) -> bool {
+    let words = line
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c| matches!(c, '\'' | '"' | '`' | '(' | ')' | '$')))
+        .collect::<Vec<_>>();
+    words.windows(2).any(|pair| {
+        pair[1] == "alias"
+            && Path::new(pair[0])
+                .file_name()
+                .is_some_and(|name| name == "theshit")
+    })
+}
+
+/// Removes the setup of any shell from a config file given by the user, like `--rc-file`.
+pub fn remove_setup_from(config_path: &Path, dry_run: bool) -> Result<Vec<Removal>> {
+    remove_setup(config_path, is_setup_line, dry_run)
+}
+
+/// Removes the theshit block and the lines matching `is_setup_line` from the config file, if it exists.
+pub fn remove_setup(
+    config_path: &Path,
+    is_setup_line: impl Fn(&str) -> bool,
+    dry_run: bool,
+) -> Result<Vec<Removal>> {
+    let config_path = &match resolve_symlink(config_path) {
+        Ok(config_path) => config_path,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let content = match fs::read_to_string(config_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let (kept, _, setup_lines) = split_setup(&content, is_setup_line);
+    let updated = kept.concat();
+    if updated != content && !dry_run {
+        backup(config_path)?;
+        write_atomically(config_path, &updated)?;
+    }
+    Ok(setup_lines
+        .into_iter()
+        .map(|line| Removal {
+            path: config_path.to_path_buf(),
+            line: Some(line),
+        })
+        .collect())
+}
+
+/// Parses aliases exported one per line as `name<TAB>expansion`.
+pub fn parse_tab_separated_aliases(raw_aliases: &str) -> HashMap<String, String> {
+    raw_aliases
+        .split('\n')
+        .filter_map(|raw_alias| raw_alias.split_once('\t'))
+        .filter(|(name, _)| !name.is_empty())
+        .map(|(name, expansion)| (name.to_string(), expansion.to_string()))
+        .collect()
+}
+
+/// Reads the names of shell functions exported one per line in `SH_SHELL_FUNCTIONS`.
+pub fn get_functions() -> HashSet<String> {
+    parse_functions(&env::var("SH_SHELL_FUNCTIONS").unwrap_or_default())
+}
+
+fn parse_functions(raw_functions: &str) -> HashSet<String> {
+    raw_functions
+        .split('\n')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_setup_line() {
+        for line in [
+            "eval $( /home/user/.cargo/bin/theshit alias shit)",
+            "eval $( /usr/bin/theshit alias fix --replace-history)",
+            "/usr/bin/theshit alias shit | source",
+            "Invoke-Expression (& '/usr/bin/theshit' alias shit | Out-String)",
+            "execx($('/usr/bin/theshit' alias shit))",
+            "eval ((external '/usr/bin/theshit') alias shit | slurp)",
+            "eval `/usr/bin/theshit alias shit`",
+        ] {
+            assert!(is_setup_line(line), "{line}");
+        }
+        assert!(!is_setup_line("alias ll='ls -la'"));
+        assert!(!is_setup_line("export PATH=$PATH:/opt/theshit"));
+    }
+
+    #[test]
+    fn test_remove_setup() {
+        let directory = tempfile::tempdir().unwrap();
+        let config_path = directory.path().join(".bashrc");
+        let content =
+            "alias ll='ls -la'\neval $( /usr/bin/theshit alias shit)\nexport EDITOR=vim\n";
+        fs::write(&config_path, content).unwrap();
+
+        let removals = remove_setup(&config_path, is_setup_line, true).unwrap();
+        assert_eq!(removals.len(), 1);
+        assert_eq!(
+            removals[0].line.as_deref(),
+            Some("eval $( /usr/bin/theshit alias shit)")
+        );
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), content);
+
+        remove_setup(&config_path, is_setup_line, false).unwrap();
+        assert_eq!(
+            fs::read_to_string(&config_path).unwrap(),
+            "alias ll='ls -la'\nexport EDITOR=vim\n"
+        );
+        assert!(
+            remove_setup(&config_path, is_setup_line, false)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_remove_setup_block() {
+        let directory = tempfile::tempdir().unwrap();
+        let config_path = directory.path().join("config.fish");
+        let content = "set -x EDITOR vim\n# >>> theshit >>>\n/usr/bin/theshit alias shit | source\n# <<< theshit <<<\n";
+        fs::write(&config_path, content).unwrap();
+
+        let removals = remove_setup(&config_path, is_setup_line, false).unwrap();
+        assert_eq!(removals.len(), 1);
+        assert_eq!(
+            fs::read_to_string(&config_path).unwrap(),
+            "set -x EDITOR vim\n"
+        );
+        assert_eq!(
+            fs::read_to_string(directory.path().join("config.fish.theshit.bak")).unwrap(),
+            content
+        );
+    }
+
+    #[test]
+    fn test_setup_alias_writes_block() {
+        let directory = tempfile::tempdir().unwrap();
+        let config_path = directory.path().join(".bashrc");
+        fs::write(&config_path, "export EDITOR=vim").unwrap();
+
+        setup_alias(
+            "eval $( /usr/bin/theshit alias shit)".to_string(),
+            &config_path,
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&config_path).unwrap(),
+            "export EDITOR=vim\n# >>> theshit >>>\neval $( /usr/bin/theshit alias shit)\n# <<< theshit <<<\n"
+        );
+        assert_eq!(
+            fs::read_to_string(directory.path().join(".bashrc.theshit.bak")).unwrap(),
+            "export EDITOR=vim"
+        );
+        let result = setup_alias(
+            "eval $( /usr/bin/theshit alias shit)".to_string(),
+            &config_path,
+        );
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::AlreadyExists);
+    }
 
-    writeln!(config_file, "{setup_command}")
+    #[test]
+    fn test_setup_alias_replaces_block_in_place() {
+        let directory = tempfile::tempdir().unwrap();
+        let config_path = directory.path().join(".zshrc");
+        fs::write(
+            &config_path,
+            "# >>> theshit >>>\neval $( /usr/bin/theshit alias shit)\n# <<< theshit <<<\nexport EDITOR=vim\n",
+        )
+        .unwrap();
+
+        setup_alias(
+            "eval $( /home/user/.cargo/bin/theshit alias fix)".to_string(),
+            &config_path,
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&config_path).unwrap(),
+            "# >>> theshit >>>\neval $( /home/user/.cargo/bin/theshit alias fix)\n# <<< theshit <<<\nexport EDITOR=vim\n"
+        );
+    }
+
+    #[test]
+    fn test_setup_alias_replaces_old_setup_line() {
+        let directory = tempfile::tempdir().unwrap();
+        let config_path = directory.path().join(".bashrc");
+        fs::write(
+            &config_path,
+            "eval $( /usr/bin/theshit alias shit)\nexport EDITOR=vim\n",
+        )
+        .unwrap();
+
+        setup_alias(
+            "eval $( /usr/local/bin/theshit alias shit)".to_string(),
+            &config_path,
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&config_path).unwrap(),
+            "export EDITOR=vim\n# >>> theshit >>>\neval $( /usr/local/bin/theshit alias shit)\n# <<< theshit <<<\n"
+        );
+    }
+
+    #[test]
+    fn test_setup_alias_ignores_unterminated_block() {
+        let directory = tempfile::tempdir().unwrap();
+        let config_path = directory.path().join(".bashrc");
+        fs::write(&config_path, "# >>> theshit >>>\nexport EDITOR=vim\n").unwrap();
+
+        setup_alias(
+            "eval $( /usr/bin/theshit alias shit)".to_string(),
+            &config_path,
+        )
+        .unwrap();
+        let expected = "# >>> theshit >>>\nexport EDITOR=vim\n\
+                        # >>> theshit >>>\neval $( /usr/bin/theshit alias shit)\n# <<< theshit <<<\n";
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), expected);
+
+        // The stray start marker now comes before a complete block, the lines between stay
+        let result = setup_alias(
+            "eval $( /usr/bin/theshit alias shit --key-binding)".to_string(),
+            &config_path,
+        );
+        assert!(result.is_ok());
+        assert_eq!(
+            fs::read_to_string(&config_path).unwrap(),
+            expected.replace("alias shit)", "alias shit --key-binding)")
+        );
+        remove_setup(&config_path, is_setup_line, false).unwrap();
+        assert_eq!(
+            fs::read_to_string(&config_path).unwrap(),
+            "# >>> theshit >>>\nexport EDITOR=vim\n"
+        );
+    }
+
+    #[test]
+    fn test_setup_alias_follows_symlink() {
+        let directory = tempfile::tempdir().unwrap();
+        let dotfile_path = directory.path().join("dotfiles/bashrc");
+        fs::create_dir_all(dotfile_path.parent().unwrap()).unwrap();
+        fs::write(&dotfile_path, "export EDITOR=vim\n").unwrap();
+        let config_path = directory.path().join(".bashrc");
+        std::os::unix::fs::symlink(&dotfile_path, &config_path).unwrap();
+
+        setup_alias(
+            "eval $( /usr/bin/theshit alias shit)".to_string(),
+            &config_path,
+        )
+        .unwrap();
+        assert!(
+            fs::symlink_metadata(&config_path)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+        assert!(
+            fs::read_to_string(&dotfile_path)
+                .unwrap()
+                .contains("eval $( /usr/bin/theshit alias shit)")
+        );
+        assert!(
+            directory
+                .path()
+                .join("dotfiles/bashrc.theshit.bak")
+                .exists()
+        );
+    }
+
+    #[test]
+    fn test_setup_alias_rejects_dangling_symlink() {
+        let directory = tempfile::tempdir().unwrap();
+        let config_path = directory.path().join(".bashrc");
+        std::os::unix::fs::symlink(directory.path().join("missing"), &config_path).unwrap();
+
+        assert!(
+            setup_alias(
+                "eval $( /usr/bin/theshit alias shit)".to_string(),
+                &config_path
+            )
+            .is_err()
+        );
+        assert!(
+            fs::symlink_metadata(&config_path)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+    }
+
+    #[test]
+    fn test_write_setup_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("conf.d/theshit.fish");
+
+        write_setup_file("/usr/bin/theshit alias shit | source", &path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# >>> theshit >>>\n/usr/bin/theshit alias shit | source\n# <<< theshit <<<\n"
+        );
+        let result = write_setup_file("/usr/bin/theshit alias shit | source", &path);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn test_remove_setup_missing_config() {
+        let directory = tempfile::tempdir().unwrap();
+        let config_path = directory.path().join(".zshrc");
+        assert!(
+            remove_setup(&config_path, is_setup_line, false)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(!config_path.exists());
+    }
+
+    #[test]
+    fn test_parse_functions() {
+        let functions = parse_functions("mkcd\n extract \n\n");
+        assert_eq!(functions.len(), 2);
+        assert!(functions.contains("mkcd"));
+        assert!(functions.contains("extract"));
+    }
 }
@@ -1,4 +1,5 @@
 use super::enums::Shell;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::{env, process};
 use sysinfo::{Pid, ProcessRefreshKind, RefreshKind, System};
@@ -7,24 +8,47 @@ pub fn get_current_shell() -> Option<Shell> {
     get_current_shell_by_env().or_else(get_current_shell_by_process)
 }
 
-fn get_current_shell_by_env() -> Option<Shell> {
+/// Working directory the failed command ran in, as passed by the shell function.
+pub fn get_previous_cwd() -> Option<PathBuf> {
+    env::var_os("SH_PREV_CWD")
+        .map(PathBuf::from)
+        .filter(|cwd| cwd.is_dir())
+}
+
+/// Exit statuses of the commands in the last pipeline the shell ran, empty if the shell doesn't report them.
+pub fn get_previous_pipe_status() -> Vec<i32> {
+    env::var("SH_PREV_PIPESTATUS")
+        .unwrap_or_default()
+        .split_whitespace()
+        .map_while(|status| status.parse().ok())
+        .collect()
+}
+
+pub fn get_current_shell_by_env() -> Option<Shell> {
     env::var("SH_SHELL")
         .ok()
         .and_then(|shell| Shell::from_str(shell.as_str()).ok())
 }
 
-fn get_current_shell_by_process() -> Option<Shell> {
+pub fn get_current_shell_by_process() -> Option<Shell> {
     let mut system = System::new();
     system
         .refresh_specifics(RefreshKind::nothing().with_processes(ProcessRefreshKind::everything()));
     let mut current_process = system.process(Pid::from_u32(process::id()));
     loop {
         let process = current_process?;
+        // Shells run by an interpreter (like xonsh) are only recognisable by the process name
+        // or by the script passed to the interpreter
         let result: Option<Shell> = process
             .exe()
-            .and_then(|path| path.file_name())
-            .and_then(|name| name.to_str())
-            .and_then(|name_str| Shell::from_str(name_str).ok());
+            .and_then(get_shell_by_executable)
+            .or_else(|| get_shell_by_executable(Path::new(process.name())))
+            .or_else(|| {
+                process
+                    .cmd()
+                    .get(1)
+                    .and_then(|script| get_shell_by_executable(Path::new(script)))
+            });
         match result {
             Some(_) => return result,
             None => {
@@ -34,6 +58,12 @@ fn get_current_shell_by_process() -> Option<Shell> {
     }
 }
 
+fn get_shell_by_executable(path: &Path) -> Option<Shell> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name_str| Shell::from_str(name_str).ok())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -77,6 +107,92 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_get_current_shell_by_env_nushell() {
+        unsafe {
+            env::set_var("SH_SHELL", "nushell");
+        }
+        let shell = get_current_shell_by_env();
+        assert!(shell.is_some());
+        assert!(matches!(shell.unwrap(), Shell::Nushell));
+        unsafe {
+            env::remove_var("SH_SHELL");
+        }
+    }
+
+    #[test]
+    fn test_get_shell_by_executable() {
+        assert!(matches!(
+            get_shell_by_executable(Path::new("/usr/bin/bash")),
+            Some(Shell::Bash)
+        ));
+        assert!(matches!(
+            get_shell_by_executable(Path::new("/home/user/.cargo/bin/nu")),
+            Some(Shell::Nushell)
+        ));
+        assert!(matches!(
+            get_shell_by_executable(Path::new("/opt/microsoft/powershell/7/pwsh")),
+            Some(Shell::PowerShell)
+        ));
+        for (path, expected) in [
+            ("/usr/bin/xonsh", "xonsh"),
+            ("/usr/bin/elvish", "elvish"),
+            ("/bin/tcsh", "tcsh"),
+        ] {
+            assert_eq!(
+                get_shell_by_executable(Path::new(path)).map(|shell| shell.as_ref().to_string()),
+                Some(expected.to_string())
+            );
+        }
+        assert!(get_shell_by_executable(Path::new("/usr/bin/python3")).is_none());
+        assert!(get_shell_by_executable(Path::new("/usr/bin/theshit")).is_none());
+    }
+
+    #[test]
+    fn test_get_current_shell_by_env_new_shells() {
+        for (name, expected) in [("xonsh", "xonsh"), ("elvish", "elvish"), ("tcsh", "tcsh")] {
+            unsafe {
+                env::set_var("SH_SHELL", name);
+            }
+            let shell = get_current_shell_by_env();
+            assert_eq!(
+                shell.map(|shell| shell.as_ref().to_string()),
+                Some(expected.to_string())
+            );
+        }
+        unsafe {
+            env::remove_var("SH_SHELL");
+        }
+    }
+
+    #[test]
+    fn test_get_previous_pipe_status() {
+        unsafe {
+            env::set_var("SH_PREV_PIPESTATUS", "1 0 141");
+        }
+        assert_eq!(get_previous_pipe_status(), vec![1, 0, 141]);
+        unsafe {
+            env::remove_var("SH_PREV_PIPESTATUS");
+        }
+        assert!(get_previous_pipe_status().is_empty());
+    }
+
+    #[test]
+    fn test_get_previous_cwd() {
+        let cwd = tempfile::tempdir().unwrap();
+        unsafe {
+            env::set_var("SH_PREV_CWD", cwd.path());
+        }
+        assert_eq!(get_previous_cwd(), Some(cwd.path().to_path_buf()));
+        unsafe {
+            env::set_var("SH_PREV_CWD", "/nonexistent_dir_12345");
+        }
+        assert_eq!(get_previous_cwd(), None);
+        unsafe {
+            env::remove_var("SH_PREV_CWD");
+        }
+    }
+
     #[test]
     fn test_get_current_shell_by_env_invalid() {
         unsafe {
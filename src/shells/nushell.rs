@@ -0,0 +1,144 @@
+use crate::shells::generic::{self, Removal};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+/// Nushell has no `eval`, the fix runs in a child `nu`: a `cd` or an environment change
+/// in it doesn't reach the calling shell.
+pub fn get_shell_function(name: &str, path: &Path) -> String {
+    format!(
+        "
+def {name} [...args] {{
+    # The last history entry is the call of this command itself
+    let prev_cmd = (history | last 2 | first | get command);
+    let aliases = (
+        scope aliases | each {{|alias| $\"($alias.name)\\t($alias.expansion)\" }} | str join \"\\n\"
+    );
+    let functions = (scope commands | where type == custom | get name | str join \"\\n\");
+
+    let cmd = (with-env {{ SH_SHELL: nushell, SH_PREV_CMD: $prev_cmd, SH_PREV_CWD: $env.PWD, SH_SHELL_ALIASES: $aliases, SH_SHELL_FUNCTIONS: $functions }} {{
+        ^'{}' fix ...$args
+    }});
+
+    if ($cmd | is-not-empty) {{
+        nu --commands $cmd;
+    }}
+}}
+    ",
+        path.display()
+    )
+    .trim()
+    .to_string()
+}
+
+/// Nushell can only `source` files known at parse time,
+/// so the function is saved next to `config.nu` and sourced from there.
+fn config_dir() -> Result<PathBuf> {
+    Ok(dirs::config_dir()
+        .ok_or(ErrorKind::NotFound)?
+        .join("nushell"))
+}
+
+pub fn config_path() -> Result<PathBuf> {
+    Ok(config_dir()?.join("config.nu"))
+}
+
+/// The function is written to its own file, which `config.nu` sources.
+pub fn function_path() -> Result<PathBuf> {
+    Ok(config_dir()?.join("theshit.nu"))
+}
+
+pub fn setup_alias(name: &str, program_path: &Path, config_path: &Path) -> Result<()> {
+    let function_path = function_path()?;
+    fs::create_dir_all(config_dir()?)?;
+    fs::write(&function_path, get_shell_function(name, program_path))?;
+    generic::setup_alias(format!("source '{}'", function_path.display()), config_path)
+}
+
+pub fn remove_setup(dry_run: bool) -> Result<Vec<Removal>> {
+    let function_path = function_path()?;
+    let source_line = format!("source '{}'", function_path.display());
+    let mut removals = generic::remove_setup(&config_path()?, |line| line == source_line, dry_run)?;
+    if function_path.exists() {
+        if !dry_run {
+            fs::remove_file(&function_path)?;
+        }
+        removals.push(Removal {
+            path: function_path,
+            line: None,
+        });
+    }
+    Ok(removals)
+}
+
+pub fn get_aliases() -> HashMap<String, String> {
+    let raw_aliases = env::var("SH_SHELL_ALIASES").unwrap_or(String::from(""));
+    generic::parse_tab_separated_aliases(&raw_aliases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_get_shell_function_contains_name() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path);
+        assert!(result.contains("def shit [...args]"));
+    }
+
+    #[test]
+    fn test_get_shell_function_contains_path() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path);
+        assert!(result.contains("^'/usr/bin/theshit' fix"));
+    }
+
+    #[test]
+    fn test_get_shell_function_exports_shell_type() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path);
+        assert!(result.contains("SH_SHELL: nushell"));
+    }
+
+    #[test]
+    fn test_get_aliases_empty() {
+        let aliases = get_aliases();
+        assert!(aliases.is_empty());
+    }
+
+    #[test]
+    fn test_get_aliases_with_env() {
+        unsafe {
+            env::set_var("SH_SHELL_ALIASES", "ll\tls -l\nla\tls -la");
+        }
+        let aliases = get_aliases();
+        assert_eq!(aliases.get("ll"), Some(&"ls -l".to_string()));
+        assert_eq!(aliases.get("la"), Some(&"ls -la".to_string()));
+        unsafe {
+            env::remove_var("SH_SHELL_ALIASES");
+        }
+    }
+
+    #[test]
+    fn test_get_aliases_with_quotes() {
+        unsafe {
+            env::set_var(
+                "SH_SHELL_ALIASES",
+                "gs\tgit status --short\ngrep\tgrep --color=\"auto\"",
+            );
+        }
+        let aliases = get_aliases();
+        assert_eq!(aliases.get("gs"), Some(&"git status --short".to_string()));
+        assert_eq!(
+            aliases.get("grep"),
+            Some(&"grep --color=\"auto\"".to_string())
+        );
+        unsafe {
+            env::remove_var("SH_SHELL_ALIASES");
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::shells::generic;
+use std::collections::HashMap;
+use std::env;
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+pub fn get_shell_function(name: &str, path: &Path) -> String {
+    format!(
+        "
+function {name} {{
+    $env:SH_SHELL = 'powershell';
+    $env:SH_PREV_CMD = (Get-History -Count 1).CommandLine;
+    $env:SH_PREV_CWD = (Get-Location).Path;
+    $env:SH_SHELL_ALIASES = (Get-Alias | ForEach-Object {{ \"$($_.Name)`t$($_.Definition)\" }}) -join \"`n\";
+    $env:SH_SHELL_FUNCTIONS = (Get-Command -CommandType Function | ForEach-Object Name) -join \"`n\";
+
+    $SH_CMD = & '{}' fix @args;
+    if ($SH_CMD) {{
+        Invoke-Expression ($SH_CMD -join \"`n\");
+    }}
+
+    Remove-Item Env:SH_SHELL_FUNCTIONS, Env:SH_SHELL_ALIASES, Env:SH_PREV_CWD, Env:SH_PREV_CMD, Env:SH_SHELL -ErrorAction SilentlyContinue;
+}}
+    ",
+        path.display()
+    )
+    .trim()
+    .to_string()
+}
+
+/// Writes to the profile `$PROFILE` points to by default.
+pub fn config_path() -> Result<PathBuf> {
+    Ok(dirs::config_dir()
+        .ok_or(ErrorKind::NotFound)?
+        .join("powershell/Microsoft.PowerShell_profile.ps1"))
+}
+
+pub fn setup_alias(name: &str, program_path: &Path, config_path: &Path) -> Result<()> {
+    generic::setup_alias(
+        format!(
+            "Invoke-Expression (& '{}' alias {} | Out-String)",
+            program_path.display(),
+            name
+        ),
+        config_path,
+    )
+}
+
+pub fn get_aliases() -> HashMap<String, String> {
+    let raw_aliases = env::var("SH_SHELL_ALIASES").unwrap_or(String::from(""));
+    generic::parse_tab_separated_aliases(&raw_aliases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_get_shell_function_contains_name() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path);
+        assert!(result.contains("function shit {"));
+    }
+
+    #[test]
+    fn test_get_shell_function_contains_path() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path);
+        assert!(result.contains("& '/usr/bin/theshit' fix @args"));
+    }
+
+    #[test]
+    fn test_get_shell_function_exports_shell_type() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path);
+        assert!(result.contains("$env:SH_SHELL = 'powershell'"));
+    }
+
+    #[test]
+    fn test_get_aliases_empty() {
+        let aliases = get_aliases();
+        assert!(aliases.is_empty());
+    }
+
+    #[test]
+    fn test_get_aliases_with_env() {
+        unsafe {
+            env::set_var("SH_SHELL_ALIASES", "gci\tGet-ChildItem\nls\tGet-ChildItem");
+        }
+        let aliases = get_aliases();
+        assert_eq!(aliases.get("gci"), Some(&"Get-ChildItem".to_string()));
+        assert_eq!(aliases.get("ls"), Some(&"Get-ChildItem".to_string()));
+        unsafe {
+            env::remove_var("SH_SHELL_ALIASES");
+        }
+    }
+
+    #[test]
+    fn test_get_aliases_ignores_invalid_format() {
+        unsafe {
+            env::set_var("SH_SHELL_ALIASES", "not_an_alias\n%\tForEach-Object");
+        }
+        let aliases = get_aliases();
+        assert_eq!(aliases.get("%"), Some(&"ForEach-Object".to_string()));
+        assert_eq!(aliases.get("not_an_alias"), None);
+        unsafe {
+            env::remove_var("SH_SHELL_ALIASES");
+        }
+    }
+}
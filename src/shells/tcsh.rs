@@ -0,0 +1,97 @@
+use crate::shells::generic;
+use std::collections::HashMap;
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// tcsh has no functions, so this is a one-line alias.
+pub fn get_shell_function(name: &str, path: &Path) -> String {
+    format!(
+        "alias {name} 'setenv SH_SHELL tcsh && setenv SH_PREV_CMD \"`history -h 2 | head -n 1`\" && setenv SH_PREV_CWD \"$cwd\" && set SH_CMD=\"`{} fix \\!*`\" && eval \"$SH_CMD\"; unsetenv SH_PREV_CWD; unsetenv SH_PREV_CMD; unsetenv SH_SHELL'",
+        path.display()
+    )
+}
+
+pub fn config_path() -> Result<PathBuf> {
+    Ok(dirs::home_dir().ok_or(ErrorKind::NotFound)?.join(".tcshrc"))
+}
+
+pub fn setup_alias(name: &str, program_path: &Path, config_path: &Path) -> Result<()> {
+    generic::setup_alias(
+        format!("eval `{} alias {}`", program_path.display(), name),
+        config_path,
+    )
+}
+
+/// The output of `alias` can't be passed through `setenv` as it spans multiple lines,
+/// so the aliases are read from an interactive tcsh instead.
+pub fn get_aliases() -> HashMap<String, String> {
+    let raw_aliases = Command::new("tcsh")
+        .args(["-ic", "alias"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+        .unwrap_or_default();
+    parse_aliases(&raw_aliases)
+}
+
+/// `alias` prints `name<TAB>value`, values of several words are wrapped in parentheses.
+fn parse_aliases(raw_aliases: &str) -> HashMap<String, String> {
+    generic::parse_tab_separated_aliases(raw_aliases)
+        .into_iter()
+        .map(|(name, value)| {
+            let value = match value.strip_prefix('(').and_then(|v| v.strip_suffix(')')) {
+                Some(value) => value.to_string(),
+                None => value,
+            };
+            (name, value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_get_shell_function_contains_name() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path);
+        assert!(result.starts_with("alias shit '"));
+    }
+
+    #[test]
+    fn test_get_shell_function_contains_path() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path);
+        assert!(result.contains("`/usr/bin/theshit fix \\!*`"));
+    }
+
+    #[test]
+    fn test_get_shell_function_exports_shell_type() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path);
+        assert!(result.contains("setenv SH_SHELL tcsh"));
+    }
+
+    #[test]
+    fn test_parse_aliases_empty() {
+        let aliases = parse_aliases("");
+        assert!(aliases.is_empty());
+    }
+
+    #[test]
+    fn test_parse_aliases_single_word() {
+        let aliases = parse_aliases("cls\tclear\n");
+        assert_eq!(aliases.get("cls"), Some(&"clear".to_string()));
+    }
+
+    #[test]
+    fn test_parse_aliases_with_parentheses() {
+        let aliases = parse_aliases("ll\t(ls -l)\ngrep\t(grep --color=auto)");
+        assert_eq!(aliases.get("ll"), Some(&"ls -l".to_string()));
+        assert_eq!(aliases.get("grep"), Some(&"grep --color=auto".to_string()));
+    }
+}
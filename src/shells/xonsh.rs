@@ -0,0 +1,104 @@
+use crate::shells::generic;
+use std::collections::HashMap;
+use std::env;
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+pub fn get_shell_function(name: &str, path: &Path) -> String {
+    format!(
+        "
+def _theshit(args):
+    $SH_SHELL = 'xonsh'
+    $SH_PREV_CMD = __xonsh__.history[-1].cmd.strip()
+    $SH_PREV_CWD = $PWD
+    $SH_SHELL_ALIASES = '\\n'.join(
+        alias + '\\t' + ' '.join(value)
+        for alias, value in aliases.items()
+        if isinstance(value, list)
+    )
+    # Callable aliases are Python functions, they only run inside xonsh
+    $SH_SHELL_FUNCTIONS = '\\n'.join(
+        alias for alias, value in aliases.items() if callable(value)
+    )
+
+    sh_cmd = $('{}' fix @(args)).strip()
+
+    del $SH_SHELL_FUNCTIONS
+    del $SH_SHELL_ALIASES
+    del $SH_PREV_CWD
+    del $SH_PREV_CMD
+    del $SH_SHELL
+    if sh_cmd:
+        execx(sh_cmd)
+
+aliases['{name}'] = _theshit
+    ",
+        path.display()
+    )
+    .trim()
+    .to_string()
+}
+
+pub fn config_path() -> Result<PathBuf> {
+    Ok(dirs::home_dir()
+        .ok_or(ErrorKind::NotFound)?
+        .join(".xonshrc"))
+}
+
+pub fn setup_alias(name: &str, program_path: &Path, config_path: &Path) -> Result<()> {
+    generic::setup_alias(
+        format!("execx($('{}' alias {}))", program_path.display(), name),
+        config_path,
+    )
+}
+
+pub fn get_aliases() -> HashMap<String, String> {
+    let raw_aliases = env::var("SH_SHELL_ALIASES").unwrap_or(String::from(""));
+    generic::parse_tab_separated_aliases(&raw_aliases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_get_shell_function_contains_name() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path);
+        assert!(result.contains("aliases['shit'] = _theshit"));
+    }
+
+    #[test]
+    fn test_get_shell_function_contains_path() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path);
+        assert!(result.contains("$('/usr/bin/theshit' fix @(args))"));
+    }
+
+    #[test]
+    fn test_get_shell_function_exports_shell_type() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path);
+        assert!(result.contains("$SH_SHELL = 'xonsh'"));
+    }
+
+    #[test]
+    fn test_get_aliases_empty() {
+        let aliases = get_aliases();
+        assert!(aliases.is_empty());
+    }
+
+    #[test]
+    fn test_get_aliases_with_env() {
+        unsafe {
+            env::set_var("SH_SHELL_ALIASES", "ll\tls -l\nla\tls -la");
+        }
+        let aliases = get_aliases();
+        assert_eq!(aliases.get("ll"), Some(&"ls -l".to_string()));
+        assert_eq!(aliases.get("la"), Some(&"ls -la".to_string()));
+        unsafe {
+            env::remove_var("SH_SHELL_ALIASES");
+        }
+    }
+}
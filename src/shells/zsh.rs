@@ -1,27 +1,98 @@
-use crate::shells::generic;
+use crate::shells::generic::{self, AliasOptions};
 use std::collections::HashMap;
 use std::env;
 use std::io::{ErrorKind, Result};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-pub fn get_shell_function(name: &str, path: &Path) -> String {
+/// The setup evaluates this unquoted, so it ends up on one line: no comments,
+/// every statement ends with `;`. `pipestatus` is read first, every command overwrites it,
+/// and in instant mode the hooks mark where the output of each command starts and ends.
+pub fn get_shell_function(name: &str, path: &Path, options: AliasOptions) -> String {
+    // zsh can't delete history entries, the hook keeps the alias call from being saved
+    let skip_history = if options.replace_history {
+        format!(
+            "
+__theshit_skip_history() {{ [[ ${{1%%$'\\n'}} != {name} && ${{1%%$'\\n'}} != '{name} '* ]]; }};
+autoload -Uz add-zsh-hook;
+add-zsh-hook zshaddhistory __theshit_skip_history;"
+        )
+    } else {
+        String::new()
+    };
+    // The command on the line hasn't run, it is fixed by its text alone
+    let key_binding = if options.key_binding {
+        format!(
+            "
+__theshit_widget() {{
+    local SH_CMD;
+    zle -I;
+    SH_CMD=$(
+      export SH_SHELL=zsh;
+      if [[ -n $BUFFER ]]; then
+        export SH_PREV_CMD=$BUFFER;
+        export SH_PREV_CWD=\"$PWD\";
+        set -- --text-only;
+      else
+        export SH_PREV_CMD=\"$(fc -ln -1)\";
+        export SH_PREV_CWD=\"${{__theshit_prev_cwd:-$PWD}}\";
+      fi;
+      export SH_SHELL_ALIASES=\"$(alias -r)\";
+      export SH_SHELL_GLOBAL_ALIASES=\"$(alias -g)\";
+      export SH_SHELL_SUFFIX_ALIASES=\"$(alias -s)\";
+      export SH_SHELL_FUNCTIONS=${{(F)${{(k)functions}}}};
+      {} fix \"$@\" </dev/tty
+    ) && BUFFER=$SH_CMD && CURSOR=$#BUFFER;
+    zle reset-prompt;
+}};
+zle -N __theshit_widget;
+bindkey '\\e\\e' __theshit_widget;",
+            path.display()
+        )
+    } else {
+        String::new()
+    };
     format!(
         "
 {name}() {{
+    export SH_PREV_PIPESTATUS=\"${{pipestatus[*]}}\";
     export SH_SHELL=zsh;
     SH_PREV_CMD=\"$(fc -ln -1)\";
     export SH_PREV_CMD;
-    SH_SHELL_ALIASES=$(alias);
+    export SH_PREV_CWD=\"${{__theshit_prev_cwd:-$PWD}}\";
+    SH_SHELL_ALIASES=$(alias -r);
     export SH_SHELL_ALIASES;
+    SH_SHELL_GLOBAL_ALIASES=$(alias -g);
+    export SH_SHELL_GLOBAL_ALIASES;
+    SH_SHELL_SUFFIX_ALIASES=$(alias -s);
+    export SH_SHELL_SUFFIX_ALIASES;
+    SH_SHELL_FUNCTIONS=${{(F)${{(k)functions}}}};
+    export SH_SHELL_FUNCTIONS;
 
     SH_CMD=$(
       {} fix $@
-    ) && eval \"$SH_CMD\";
+    ) && print -sr -- \"$SH_CMD\" && eval \"$SH_CMD\";
 
+    unset SH_SHELL_FUNCTIONS;
+    unset SH_SHELL_SUFFIX_ALIASES;
+    unset SH_SHELL_GLOBAL_ALIASES;
     unset SH_SHELL_ALIASES;
+    unset SH_PREV_CWD;
     unset SH_PREV_CMD;
     unset SH_SHELL;
-}}
+    unset SH_PREV_PIPESTATUS;
+}};
+{skip_history}{key_binding}
+
+__theshit_track_cwd() {{ if [[ $HISTCMD != $__theshit_histcmd ]]; then __theshit_histcmd=$HISTCMD; __theshit_prev_cwd=$__theshit_cwd; fi; __theshit_cwd=$PWD; }};
+autoload -Uz add-zsh-hook;
+add-zsh-hook precmd __theshit_track_cwd;
+
+if [[ -n \"$SH_INSTANT_LOG\" ]]; then
+    __theshit_instant_begin() {{ printf '\\033]6973;B;%s\\007' \"${{1//[[:cntrl:]]/ }}\"; }};
+    __theshit_instant_end() {{ printf '\\033]6973;E\\007'; }};
+    add-zsh-hook preexec __theshit_instant_begin;
+    add-zsh-hook precmd __theshit_instant_end;
+fi
     ",
         path.display()
     )
@@ -29,16 +100,66 @@ pub fn get_shell_function(name: &str, path: &Path) -> String {
     .to_string()
 }
 
-pub fn setup_alias(name: &str, program_path: &Path) -> Result<()> {
-    let config_path = dirs::home_dir().ok_or(ErrorKind::NotFound)?.join(".zshrc");
+/// zsh reads its config from `$ZDOTDIR` when it is set.
+pub fn config_path() -> Result<PathBuf> {
+    let config_dir = env::var_os("ZDOTDIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(dirs::home_dir)
+        .ok_or(ErrorKind::NotFound)?;
+    Ok(config_dir.join(".zshrc"))
+}
+
+pub fn setup_alias(
+    name: &str,
+    program_path: &Path,
+    options: AliasOptions,
+    config_path: &Path,
+) -> Result<()> {
     generic::setup_alias(
-        format!("eval $( {} alias {})", program_path.display(), name),
-        config_path.as_path(),
+        format!(
+            "eval $( {} alias {}{})",
+            program_path.display(),
+            name,
+            options.arguments()
+        ),
+        config_path,
     )
 }
 
 pub fn get_aliases() -> HashMap<String, String> {
-    let raw_aliases = env::var("SH_SHELL_ALIASES").unwrap_or(String::from(""));
+    parse_aliases(&env::var("SH_SHELL_ALIASES").unwrap_or(String::from("")))
+}
+
+pub fn get_global_aliases() -> HashMap<String, String> {
+    parse_aliases(&env::var("SH_SHELL_GLOBAL_ALIASES").unwrap_or(String::from("")))
+}
+
+pub fn get_suffix_aliases() -> HashMap<String, String> {
+    parse_aliases(&env::var("SH_SHELL_SUFFIX_ALIASES").unwrap_or(String::from("")))
+}
+
+/// `alias -r`, `-g` and `-s` print bare `name=value` lines, so the matching `alias` is prepended.
+pub fn get_alias_definitions() -> String {
+    [
+        ("SH_SHELL_ALIASES", "alias"),
+        ("SH_SHELL_GLOBAL_ALIASES", "alias -g"),
+        ("SH_SHELL_SUFFIX_ALIASES", "alias -s"),
+    ]
+    .into_iter()
+    .flat_map(|(variable, alias)| {
+        env::var(variable)
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| line.contains('='))
+            .map(|line| format!("{alias} -- {line}"))
+            .collect::<Vec<_>>()
+    })
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+fn parse_aliases(raw_aliases: &str) -> HashMap<String, String> {
     let split_raw_aliases = raw_aliases.split('\n');
     let mut aliases: HashMap<String, String> = HashMap::new();
     for raw_alias in split_raw_aliases {
@@ -47,8 +168,9 @@ pub fn get_aliases() -> HashMap<String, String> {
         }
         if let Some((name, mut value)) = raw_alias.split_once('=') {
             let value_bytes = value.as_bytes();
-            if (value_bytes[0] == b'"' && value_bytes[value.len() - 1] == b'"')
-                || (value_bytes[0] == b'\'' && value_bytes[value.len() - 1] == b'\'')
+            if value.len() >= 2
+                && ((value_bytes[0] == b'"' && value_bytes[value.len() - 1] == b'"')
+                    || (value_bytes[0] == b'\'' && value_bytes[value.len() - 1] == b'\''))
             {
                 value = &value[1..value.len() - 1];
             }
@@ -63,27 +185,100 @@ mod tests {
     use super::*;
     use std::path::PathBuf;
 
+    #[test]
+    fn test_config_path_honours_zdotdir() {
+        unsafe { env::set_var("ZDOTDIR", "/home/user/.config/zsh") };
+        let config_path = config_path().unwrap();
+        unsafe { env::remove_var("ZDOTDIR") };
+        assert_eq!(config_path, PathBuf::from("/home/user/.config/zsh/.zshrc"));
+    }
+
     #[test]
     fn test_get_shell_function_contains_name() {
         let path = PathBuf::from("/usr/bin/theshit");
-        let result = get_shell_function("shit", &path);
+        let result = get_shell_function("shit", &path, AliasOptions::default());
         assert!(result.contains("shit()"));
     }
 
     #[test]
     fn test_get_shell_function_contains_path() {
         let path = PathBuf::from("/usr/bin/theshit");
-        let result = get_shell_function("shit", &path);
+        let result = get_shell_function("shit", &path, AliasOptions::default());
         assert!(result.contains("/usr/bin/theshit"));
     }
 
     #[test]
     fn test_get_shell_function_exports_shell_type() {
         let path = PathBuf::from("/usr/bin/theshit");
-        let result = get_shell_function("shit", &path);
+        let result = get_shell_function("shit", &path, AliasOptions::default());
         assert!(result.contains("export SH_SHELL=zsh"));
     }
 
+    // The setup runs `eval $(theshit alias)` unquoted, which puts the function on one line.
+    // bash parses the zsh function as well, so it checks both.
+    #[test]
+    fn test_get_shell_function_evaluates_on_one_line() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let replace_history = AliasOptions {
+            replace_history: true,
+            ..Default::default()
+        };
+        let key_binding = AliasOptions {
+            key_binding: true,
+            ..Default::default()
+        };
+        for options in [AliasOptions::default(), replace_history, key_binding] {
+            let result = get_shell_function("shit", &path, options);
+            assert!(
+                !result
+                    .lines()
+                    .any(|line| line.trim_start().starts_with('#'))
+            );
+            // Commands of the other shell may fail, only the syntax matters
+            let output = std::process::Command::new("bash")
+                .args([
+                    "--norc",
+                    "-c",
+                    "eval $(printf '%s' \"$1\"); declare -F shit",
+                ])
+                .arg("bash")
+                .arg(&result)
+                .env_remove("SH_INSTANT_LOG")
+                .output()
+                .unwrap();
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(!stderr.contains("syntax error"), "{stderr}");
+            assert_eq!(String::from_utf8_lossy(&output.stdout), "shit\n");
+        }
+    }
+
+    #[test]
+    fn test_get_shell_function_pushes_history() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path, AliasOptions::default());
+        assert!(result.contains("print -sr -- \"$SH_CMD\""));
+        assert!(!result.contains("zshaddhistory"));
+        let options = AliasOptions {
+            replace_history: true,
+            ..Default::default()
+        };
+        let result = get_shell_function("shit", &path, options);
+        assert!(result.contains("add-zsh-hook zshaddhistory __theshit_skip_history"));
+    }
+
+    #[test]
+    fn test_get_shell_function_key_binding() {
+        let path = PathBuf::from("/usr/bin/theshit");
+        let result = get_shell_function("shit", &path, AliasOptions::default());
+        assert!(!result.contains("__theshit_widget"));
+        let options = AliasOptions {
+            key_binding: true,
+            ..Default::default()
+        };
+        let result = get_shell_function("shit", &path, options);
+        assert!(result.contains("bindkey"));
+    }
+
     #[test]
     fn test_get_aliases_empty() {
         let aliases = get_aliases();
@@ -113,4 +308,34 @@ mod tests {
             env::remove_var("SH_SHELL_ALIASES");
         }
     }
+
+    #[test]
+    fn test_get_global_and_suffix_aliases() {
+        unsafe {
+            env::set_var("SH_SHELL_GLOBAL_ALIASES", "G='| grep'");
+            env::set_var("SH_SHELL_SUFFIX_ALIASES", "txt=vim");
+        }
+        assert_eq!(get_global_aliases().get("G"), Some(&"| grep".to_string()));
+        assert_eq!(get_suffix_aliases().get("txt"), Some(&"vim".to_string()));
+        unsafe {
+            env::remove_var("SH_SHELL_GLOBAL_ALIASES");
+            env::remove_var("SH_SHELL_SUFFIX_ALIASES");
+        }
+    }
+
+    #[test]
+    fn test_get_alias_definitions() {
+        unsafe {
+            env::set_var("SH_SHELL_ALIASES", "ll='ls -l'");
+            env::set_var("SH_SHELL_GLOBAL_ALIASES", "G='| grep'");
+        }
+        assert_eq!(
+            get_alias_definitions(),
+            "alias -- ll='ls -l'\nalias -g -- G='| grep'"
+        );
+        unsafe {
+            env::remove_var("SH_SHELL_ALIASES");
+            env::remove_var("SH_SHELL_GLOBAL_ALIASES");
+        }
+    }
 }